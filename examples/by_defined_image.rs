@@ -19,6 +19,13 @@ fn main() {
     panic!("Input file does not exist");
   }
 
-  let input_image = open(input_path).expect(&format!("Could not load image at {:?}", input_path));
-  segment(&input_image);
+  let input_image =
+    open(input_path).unwrap_or_else(|_| panic!("Could not load image at {:?}", input_path));
+  match segment(&input_image) {
+    Ok(segmentation) => println!(
+      "corners {:?}, rotation {:?}",
+      segmentation.corners, segmentation.rotation
+    ),
+    Err(err) => eprintln!("Could not segment board: {}", err),
+  }
 }
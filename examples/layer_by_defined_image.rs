@@ -1,4 +1,3 @@
-use image::imageops::{resize, FilterType};
 use image::open;
 use segment_chess_board::segment_layered;
 use std::path::Path;
@@ -20,7 +19,10 @@ fn main() {
     panic!("Input file does not exist");
   }
 
-  let input_image = open(input_path).expect(&format!("Could not load image at {:?}", input_path));
+  let input_image =
+    open(input_path).unwrap_or_else(|_| panic!("Could not load image at {:?}", input_path));
   let gray = input_image.into_luma8();
-  segment_layered(&gray);
+  if let Err(err) = segment_layered(&gray) {
+    eprintln!("Could not segment board: {}", err);
+  }
 }
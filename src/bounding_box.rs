@@ -1,6 +1,8 @@
+use crate::error::SegmentError;
 use std::collections::HashMap;
 
-fn points_in_bounds(points: &Vec<(f32, f32)>, (w, h): (u32, u32)) -> Vec<(f32, f32)> {
+#[allow(dead_code)]
+fn points_in_bounds(points: &[(f32, f32)], (w, h): (u32, u32)) -> Vec<(f32, f32)> {
   let mut in_bounds = Vec::new();
   for (x, y) in points.iter() {
     if *x >= 0.0 && *y >= 0.0 && *x < w as f32 && *y < h as f32 {
@@ -10,7 +12,7 @@ fn points_in_bounds(points: &Vec<(f32, f32)>, (w, h): (u32, u32)) -> Vec<(f32, f
   return in_bounds;
 }
 
-fn points_center(points: &Vec<(f32, f32)>) -> (f32, f32) {
+fn points_center(points: &[(f32, f32)]) -> (f32, f32) {
   let mut x_sum = 0.0;
   let mut y_sum = 0.0;
   for (x, y) in points.iter() {
@@ -26,14 +28,14 @@ fn angle_between_vectors(start: (f32, f32), end: (f32, f32)) -> f32 {
   let adjacent = end.0 - start.0;
   let mut angle = opposite.atan2(adjacent);
   if angle < 0.0 {
-    angle = angle + std::f32::consts::PI * 2.0;
+    angle += std::f32::consts::PI * 2.0;
   }
   let deg = angle * (180.0 / std::f32::consts::PI);
   return deg;
 }
 
-fn sorted_clockwise(p: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
-  let mut points = p.clone();
+fn sorted_clockwise(p: &[(f32, f32)]) -> Vec<(f32, f32)> {
+  let mut points = p.to_vec();
   let (cx, cy) = points_center(&points);
   points.sort_by(|a, b| {
     let a_deg = angle_between_vectors((1.0, 0.0), sub(*a, (cx, cy)));
@@ -43,8 +45,8 @@ fn sorted_clockwise(p: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
   return points;
 }
 
-fn sorted_leftmost(p: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
-  let mut points = p.clone();
+fn sorted_leftmost(p: &[(f32, f32)]) -> Vec<(f32, f32)> {
+  let mut points = p.to_vec();
   points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
   return points;
 }
@@ -86,7 +88,8 @@ fn change_basis(i: (f32, f32), j: (f32, f32), p: (f32, f32)) -> (f32, f32) {
   return (i.0 * p.0 + i.1 * p.1, j.0 * p.0 + j.1 * p.1);
 }
 
-fn transpose(points: &Vec<(f32, f32)>) -> [Vec<f32>; 2] {
+#[allow(dead_code)]
+fn transpose(points: &[(f32, f32)]) -> [Vec<f32>; 2] {
   let mut x = Vec::new();
   let mut y = Vec::new();
   for p in points.iter() {
@@ -96,23 +99,21 @@ fn transpose(points: &Vec<(f32, f32)>) -> [Vec<f32>; 2] {
   return [x, y];
 }
 
-fn rotate_points(basis: [[f32; 2]; 2], points: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
-  let transposed = transpose(points);
-  let mut x = Vec::new();
-  let mut y = Vec::new();
-  for i in 0..points.len() {
-    x.push(basis[0][0] * transposed[0][i] + basis[0][1] * transposed[1][i]);
-    y.push(basis[1][0] * transposed[0][i] + basis[1][1] * transposed[1][i]);
-  }
+#[allow(dead_code)]
+fn rotate_points(basis: [[f32; 2]; 2], points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+  let [x, y] = transpose(points);
   let mut result = Vec::new();
-  for i in 0..points.len() {
-    result.push((x[i], y[i]));
+  for (x, y) in x.iter().zip(y.iter()) {
+    result.push((
+      basis[0][0] * x + basis[0][1] * y,
+      basis[1][0] * x + basis[1][1] * y,
+    ));
   }
   return result;
 }
 
 // https://www.geometrictools.com/GTE/Mathematics/MinimumAreaBox2.h
-fn remove_colinear_points(points: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+fn remove_colinear_points(points: &[(f32, f32)]) -> Vec<(f32, f32)> {
   let mut result = Vec::new();
   let mut edge_prev = sub(points[0], points[points.len() - 1]);
   for i in 0..points.len() {
@@ -127,9 +128,9 @@ fn remove_colinear_points(points: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
 }
 
 // https://en.wikipedia.org/wiki/Gift_wrapping_algorithm
-fn convex_hull_giftwrap(points: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
-  if points.len() == 0 {
-    return Vec::new();
+fn convex_hull_giftwrap(points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+  if points.len() < 3 {
+    return points.to_vec();
   }
 
   let mut hull = Vec::new();
@@ -168,9 +169,9 @@ fn convex_hull_giftwrap(points: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
   return remove_colinear_points(&hull);
 }
 
-pub fn convex_hull_area(hull: &Vec<(f32, f32)>) -> f32 {
+pub fn convex_hull_area(hull: &[(f32, f32)]) -> f32 {
   let mut area = 0.0;
-  for i in 0..hull.len() - 1 {
+  for i in 0..hull.len().saturating_sub(1) {
     let ax = hull[i].0 - hull[0].0;
     let ay = hull[i].1 - hull[0].1;
     let bx = hull[i + 1].0 - hull[0].0;
@@ -206,6 +207,18 @@ pub fn bounding_box_sort(points: [(f32, f32); 4]) -> [(f32, f32); 4] {
   return [top[0], top[1], bottom[1], bottom[0]];
 }
 
+/// Reorders a quad so it winds clockwise in image coordinates, keeping the first point.
+pub fn quad_clockwise(points: [(f32, f32); 4]) -> [(f32, f32); 4] {
+  let mut signed_area = 0.0;
+  for i in 0..points.len() {
+    signed_area += cross_product(points[i], points[(i + 1) % points.len()]);
+  }
+  if signed_area < 0.0 {
+    return [points[0], points[3], points[2], points[1]];
+  }
+  return points;
+}
+
 pub fn bounding_box_offset(points: [(f32, f32); 4], offset: f32) -> [(f32, f32); 4] {
   let mut offsets = Vec::new();
   for i in 0..points.len() {
@@ -222,7 +235,7 @@ pub fn bounding_box_offset(points: [(f32, f32); 4], offset: f32) -> [(f32, f32);
 }
 
 // https://github.com/dbworth/minimum-area-bounding-rectangle/blob/master/python/min_bounding_rect.py
-pub fn oriented_bounding_box(ps: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
+pub fn oriented_bounding_box(ps: &[(f32, f32)]) -> [(f32, f32); 4] {
   let mut points = sorted_clockwise(ps);
   points.reverse();
 
@@ -234,17 +247,17 @@ pub fn oriented_bounding_box(ps: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
     edge_angles.push(y.atan2(x));
   }
 
-  let mut obb = (0.0, std::f32::INFINITY, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
-  for i in 0..edge_angles.len() {
-    let ri = (edge_angles[i].cos(), -edge_angles[i].sin());
-    let rj = (edge_angles[i].sin(), edge_angles[i].cos());
+  let mut obb = (0.0, f32::INFINITY, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+  for angle in edge_angles.iter() {
+    let ri = (angle.cos(), -angle.sin());
+    let rj = (angle.sin(), angle.cos());
 
     let rotated_points: Vec<(f32, f32)> = points.iter().map(|p| change_basis(ri, rj, *p)).collect();
 
-    let mut min_x = std::f32::INFINITY;
-    let mut max_x = std::f32::NEG_INFINITY;
-    let mut min_y = std::f32::INFINITY;
-    let mut max_y = std::f32::NEG_INFINITY;
+    let mut min_x = f32::INFINITY;
+    let mut max_x = f32::NEG_INFINITY;
+    let mut min_y = f32::INFINITY;
+    let mut max_y = f32::NEG_INFINITY;
     for (x, y) in rotated_points.iter() {
       if *x < min_x {
         min_x = *x;
@@ -263,16 +276,7 @@ pub fn oriented_bounding_box(ps: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
     let height = max_y - min_y;
     let area = width * height;
     if area < obb.1 {
-      obb = (
-        edge_angles[i],
-        area,
-        width,
-        height,
-        min_x,
-        max_x,
-        min_y,
-        max_y,
-      );
+      obb = (*angle, area, width, height, min_x, max_x, min_y, max_y);
     }
   }
 
@@ -293,11 +297,15 @@ pub fn oriented_bounding_box(ps: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
   ]);
 }
 
-pub fn bounding_box(points: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
+pub fn bounding_box(points: &[(f32, f32)]) -> Result<[(f32, f32); 4], SegmentError> {
+  if points.len() < 4 {
+    return Err(SegmentError::TooFewCorners(points.len()));
+  }
+
   let hull = convex_hull_giftwrap(points);
   let area = convex_hull_area(&hull);
   let alpha = (area / 15.0).sqrt();
-  let cluster_assignments = crate::cluster::dbscan(&points, alpha, 5);
+  let cluster_assignments = crate::cluster::dbscan(points, alpha, 5);
   let mut point_clusters: HashMap<usize, Vec<(f32, f32)>> = HashMap::new();
   for i in 0..points.len() {
     if cluster_assignments[i] != 0 {
@@ -317,10 +325,14 @@ pub fn bounding_box(points: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
   }
   // in some cases, no significant clusters are found. use all points
   if largest_cluster.len() < 4 {
-    largest_cluster = points.clone();
+    largest_cluster = points.to_vec();
   }
 
   let cluster_hull = convex_hull_giftwrap(&largest_cluster);
+  // every point lies on a single line
+  if cluster_hull.len() < 3 {
+    return Err(SegmentError::TooFewCorners(cluster_hull.len()));
+  }
   let mbb = oriented_bounding_box(&cluster_hull);
 
   if crate::debug::debug_images() {
@@ -372,16 +384,35 @@ pub fn bounding_box(points: &Vec<(f32, f32)>) -> [(f32, f32); 4] {
         image::Rgb::<u8>([0, 255, 100]),
       );
     }
-    crate::debug::write_rgb(&hull_image, "convex-hull-mbb");
+    crate::debug::write_rgb(&hull_image, "convex-hull-mbb")?;
   }
 
   if cluster_hull.len() == 4 {
-    return [
+    return Ok([
       cluster_hull[0],
       cluster_hull[1],
       cluster_hull[2],
       cluster_hull[3],
-    ];
+    ]);
   }
-  return mbb;
+  return Ok(mbb);
+}
+
+#[test]
+fn should_wind_quad_clockwise() {
+  let clockwise = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+  assert_eq!(quad_clockwise(clockwise), clockwise);
+  assert_eq!(
+    quad_clockwise([(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)]),
+    clockwise
+  );
+}
+
+#[test]
+fn should_reject_too_few_points() {
+  assert_eq!(convex_hull_area(&[]), 0.0);
+  assert!(matches!(
+    bounding_box(&[(0.0, 0.0), (1.0, 1.0)]),
+    Err(SegmentError::TooFewCorners(2))
+  ));
 }
@@ -0,0 +1,125 @@
+// Synthetic boards for the tests, drawn through any projection with optional pieces on top.
+
+use crate::homography::fit_projection;
+use crate::square::Square;
+use image::{GrayImage, Rgb, RgbImage};
+use imageproc::geometric_transformations::Projection;
+
+/// Dark and light squares of a wooden board.
+pub const WOOD: (Rgb<u8>, Rgb<u8>) = (Rgb([120, 80, 50]), Rgb([230, 210, 170]));
+
+/// Dark and light squares of the usual screenshot board.
+pub const GREEN: (Rgb<u8>, Rgb<u8>) = (Rgb([118, 150, 86]), Rgb([238, 238, 210]));
+
+/// A board of `squares` by `squares` squares, the top left one light, on a plain background.
+#[derive(Clone, Copy, Debug)]
+pub struct Checkerboard {
+  /// Maps image pixels to board coordinates, in squares from the top left corner of the board.
+  pub to_board: Projection,
+  pub squares: u32,
+  pub dark: Rgb<u8>,
+  pub light: Rgb<u8>,
+  pub background: Rgb<u8>,
+  /// Every pixel averages a `samples` by `samples` grid of points spread across it, as a camera
+  /// would.
+  pub samples: u32,
+}
+
+impl Checkerboard {
+  /// A board of squares `size` pixels wide centred on `centre` and turned clockwise by `angle`.
+  pub fn rotated(squares: u32, size: f32, angle: f32, centre: (f32, f32)) -> Checkerboard {
+    let half = squares as f32 / 2.0;
+    let to_board = Projection::translate(half, half)
+      * Projection::scale(1.0 / size, 1.0 / size)
+      * Projection::rotate(-angle)
+      * Projection::translate(-centre.0, -centre.1);
+    return Checkerboard::new(squares, to_board);
+  }
+
+  /// An 8x8 board whose corners, clockwise from the top left, are at `corners` in the image.
+  pub fn through(corners: [(f32, f32); 4]) -> Checkerboard {
+    let board = [(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)];
+    return Checkerboard::new(8, fit_projection(&corners, &board).unwrap());
+  }
+
+  fn new(squares: u32, to_board: Projection) -> Checkerboard {
+    return Checkerboard {
+      to_board,
+      squares,
+      dark: Rgb([40, 40, 40]),
+      light: Rgb([210, 210, 210]),
+      background: Rgb([128, 128, 128]),
+      samples: 1,
+    };
+  }
+
+  pub fn with_colors(self, (dark, light): (Rgb<u8>, Rgb<u8>)) -> Checkerboard {
+    return Checkerboard {
+      dark,
+      light,
+      ..self
+    };
+  }
+
+  pub fn rgb(&self, width: u32, height: u32) -> RgbImage {
+    return self.rgb_with_pieces(width, height, |_, _| None);
+  }
+
+  /// The board in gray, the mean of its channels.
+  pub fn gray(&self, width: u32, height: u32) -> GrayImage {
+    let rgb = self.rgb(width, height);
+    return GrayImage::from_fn(width, height, |x, y| {
+      let channels = rgb.get_pixel(x, y).0;
+      let sum: u32 = channels.iter().map(|c| *c as u32).sum();
+      image::Luma([(sum / 3) as u8])
+    });
+  }
+
+  /// The board with `piece` painting over its squares, given each square of an 8x8 board as
+  /// seen by white with a8 in the top left and the offset from the middle of the square in
+  /// squares.
+  pub fn rgb_with_pieces<F>(&self, width: u32, height: u32, piece: F) -> RgbImage
+  where
+    F: Fn(Square, (f32, f32)) -> Option<Rgb<u8>>,
+  {
+    let squares = self.squares as f32;
+    let color = |x: f32, y: f32| {
+      let (bx, by) = self.to_board * (x, y);
+      if bx < 0.0 || by < 0.0 || bx >= squares || by >= squares {
+        return self.background;
+      }
+      let (column, row) = (bx.floor(), by.floor());
+      let square = Square::new(
+        column as u8,
+        self.squares.saturating_sub(1 + row as u32) as u8,
+      );
+      let offset = (bx - column - 0.5, by - row - 0.5);
+      if let Some(color) = square.and_then(|square| piece(square, offset)) {
+        return color;
+      }
+      if (column as u32 + row as u32) % 2 == 1 {
+        return self.dark;
+      }
+      return self.light;
+    };
+
+    let samples = self.samples.max(1);
+    let count = samples * samples;
+    return RgbImage::from_fn(width, height, |x, y| {
+      let mut sum = [0u32; 3];
+      for s in 0..count {
+        let dx = ((s % samples) as f32 + 0.5) / samples as f32 - 0.5;
+        let dy = ((s / samples) as f32 + 0.5) / samples as f32 - 0.5;
+        let sample = color(x as f32 + dx, y as f32 + dy);
+        for (total, channel) in sum.iter_mut().zip(sample.0.iter()) {
+          *total += *channel as u32;
+        }
+      }
+      Rgb([
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+      ])
+    });
+  }
+}
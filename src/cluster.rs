@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use crate::spatial::GridIndex;
 
 fn dist((ax, ay): (f32, f32), (bx, by): (f32, f32)) -> f32 {
   let dist_squared = (ax - bx).powf(2.0) + (ay - by).powf(2.0);
@@ -9,17 +9,78 @@ fn gaussian_kernel(distance: f32, kernel_bandwidth: f32) -> f32 {
   return (-1.0 / 2.0 * (distance * distance) / (kernel_bandwidth * kernel_bandwidth)).exp();
 }
 
+/// Result of clustering a set of points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clustering {
+  /// Index into `centroids` of the cluster every input point belongs to, `None` for noise.
+  pub labels: Vec<Option<usize>>,
+
+  /// Indices of the input points that belong to no cluster.
+  pub noise: Vec<usize>,
+
+  /// Centre of every cluster.
+  pub centroids: Vec<(f32, f32)>,
+}
+
+impl Clustering {
+  // labels every point and places each centroid at the mean of its members
+  fn from_labels(points: &[(f32, f32)], labels: Vec<Option<usize>>) -> Clustering {
+    let count = labels.iter().flatten().map(|l| l + 1).max().unwrap_or(0);
+    let mut sums = vec![(0.0, 0.0, 0); count];
+    let mut noise = Vec::new();
+    for (index, label) in labels.iter().enumerate() {
+      match label {
+        Some(cluster) => {
+          sums[*cluster].0 += points[index].0;
+          sums[*cluster].1 += points[index].1;
+          sums[*cluster].2 += 1;
+        }
+        None => noise.push(index),
+      }
+    }
+    let centroids = sums
+      .iter()
+      .map(|(x, y, n)| (x / (*n).max(1) as f32, y / (*n).max(1) as f32))
+      .collect();
+    return Clustering {
+      labels,
+      noise,
+      centroids,
+    };
+  }
+
+  /// Number of clusters.
+  pub fn len(&self) -> usize {
+    return self.centroids.len();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.centroids.is_empty();
+  }
+
+  /// Indices of the input points in `cluster`.
+  pub fn members(&self, cluster: usize) -> Vec<usize> {
+    return (0..self.labels.len())
+      .filter(|i| self.labels[*i] == Some(cluster))
+      .collect();
+  }
+}
+
+/// A way of grouping points that lie close together.
+pub trait Clusterer {
+  fn cluster(&self, points: &[(f32, f32)]) -> Clustering;
+}
+
 fn mean_shift_shift_point(
   point: (f32, f32),
-  points: &Vec<(f32, f32)>,
+  points: &[(f32, f32)],
   kernel_bandwidth: f32,
 ) -> (f32, f32) {
   let mut shifted_x = 0.0;
   let mut shifted_y = 0.0;
   let mut total_weight = 0.0;
 
-  for i in 0..points.len() {
-    let (other_x, other_y) = points[i];
+  for (other_x, other_y) in points.iter().copied() {
     let distance = dist(point, (other_x, other_y));
     let weight = gaussian_kernel(distance, kernel_bandwidth);
     shifted_x += other_x * weight;
@@ -27,145 +88,332 @@ fn mean_shift_shift_point(
     total_weight += weight;
   }
 
+  // every other point is too far away to pull on this one
+  if total_weight == 0.0 {
+    return point;
+  }
   shifted_x /= total_weight;
   shifted_y /= total_weight;
   return (shifted_x, shifted_y);
 }
 
-fn mean_shift_shift(points: &Vec<(f32, f32)>, kernel_bandwidth: f32) -> Vec<(f32, f32)> {
-  let EPSILON = 50.0;
-  let mut shifted_points = points.clone();
-  let mut max_shift_distance = 0.0;
-  let mut stop_moving: Vec<bool> = Vec::new();
-  stop_moving.fill(false);
-  while max_shift_distance > EPSILON {
-    max_shift_distance = 0.0;
-    for i in 0..points.len() {
-      if !stop_moving[i] {
-        let point = mean_shift_shift_point(points[i], points, kernel_bandwidth);
-        let distance = dist(point, points[i]);
-        if distance > max_shift_distance {
-          max_shift_distance = distance;
+/// Clusters points around the modes of their density. The centroids are the modes the
+/// clusters converged to. Mean-shift never reports noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeanShift {
+  /// Standard deviation of the gaussian kernel, roughly the radius of a cluster.
+  pub bandwidth: f32,
+
+  /// A point has converged once a single step moves it less than this.
+  pub tolerance: f32,
+
+  /// Upper bound on the steps taken by any point.
+  pub max_iterations: usize,
+
+  /// Converged points closer than this to a cluster centre join that cluster.
+  pub merge_radius: f32,
+}
+
+impl Default for MeanShift {
+  fn default() -> Self {
+    MeanShift {
+      bandwidth: 200.0,
+      tolerance: 0.1,
+      max_iterations: 300,
+      merge_radius: 100.0,
+    }
+  }
+}
+
+impl MeanShift {
+  // moves every point uphill on the kernel density estimate of `points` until it settles
+  fn shift(&self, points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    let mut shifted_points = points.to_vec();
+    let mut stop_moving = vec![false; points.len()];
+    for _ in 0..self.max_iterations {
+      let mut moving = false;
+      for i in 0..points.len() {
+        if stop_moving[i] {
+          continue;
         }
-        if distance <= EPSILON {
+        let point = mean_shift_shift_point(shifted_points[i], points, self.bandwidth);
+        if dist(point, shifted_points[i]) <= self.tolerance {
           stop_moving[i] = true;
+        } else {
+          moving = true;
         }
         shifted_points[i] = point;
       }
+      if !moving {
+        break;
+      }
     }
+    return shifted_points;
   }
-  return shifted_points;
 }
 
-fn mean_shift_cluster(shifted_points: Vec<(f32, f32)>) -> Vec<usize> {
-  let mut clusters: Vec<(f32, f32)> = Vec::new();
-  let mut assignments: Vec<usize> = Vec::new();
-  for i in 0..shifted_points.len() {
-    for j in 0..clusters.len() {
-      let distance = dist(clusters[j], shifted_points[i]);
-      if distance < 300.0 {
-        assignments.push(j);
-        break;
-      }
+impl Clusterer for MeanShift {
+  fn cluster(&self, points: &[(f32, f32)]) -> Clustering {
+    let shifted_points = self.shift(points);
+    let mut modes: Vec<(f32, f32)> = Vec::new();
+    let mut labels = Vec::new();
+    for point in shifted_points.iter() {
+      let cluster = match modes
+        .iter()
+        .position(|m| dist(*m, *point) < self.merge_radius)
+      {
+        Some(cluster) => cluster,
+        None => {
+          modes.push(*point);
+          modes.len() - 1
+        }
+      };
+      labels.push(Some(cluster));
     }
+    // the mean of the converged points is a steadier mode than the first one to arrive
+    return Clustering::from_labels(&shifted_points, labels);
+  }
+}
+
+// https://allalgorithms.com/docs/dbscan
+/// Density based clustering. Every point gets a cluster number starting at 1, or 0 when it is
+/// noise: it has fewer than `min_pts` other points within `eps` and is not within `eps` of a
+/// point that does.
+pub fn dbscan(points: &[(f32, f32)], eps: f32, min_pts: usize) -> Vec<usize> {
+  let index = GridIndex::new(points, eps);
+  let neighbours = |i: usize| -> Vec<usize> {
+    let mut found = index.within(points[i], eps);
+    found.retain(|j| *j != i);
+    found
+  };
 
-    if assignments.len() == 0 || assignments.len() - 1 < i {
-      clusters.push(shifted_points[i]);
-      assignments.push(clusters.len());
+  let mut cluster_index = 0;
+  let mut cluster_assignments: Vec<Option<usize>> = vec![None; points.len()];
+  for i in 0..points.len() {
+    if cluster_assignments[i].is_some() {
+      continue;
+    }
+
+    let mut queue = neighbours(i);
+    if queue.len() < min_pts {
+      cluster_assignments[i] = Some(0);
+      continue;
+    }
+
+    cluster_index += 1;
+    cluster_assignments[i] = Some(cluster_index);
+    while let Some(j) = queue.pop() {
+      match cluster_assignments[j] {
+        // noise within reach of a core point is on the border of the cluster
+        Some(0) => cluster_assignments[j] = Some(cluster_index),
+        Some(_) => {}
+        None => {
+          cluster_assignments[j] = Some(cluster_index);
+          let next = neighbours(j);
+          if next.len() >= min_pts {
+            queue.extend(
+              next
+                .into_iter()
+                .filter(|k| cluster_assignments[*k].is_none_or(|assignment| assignment == 0)),
+            );
+          }
+        }
+      }
     }
   }
-  return assignments;
+
+  return cluster_assignments
+    .iter()
+    .map(|assignment| assignment.unwrap_or(0))
+    .collect();
 }
 
-pub fn mean_shift(points: &Vec<(f32, f32)>) -> Vec<usize> {
-  let shifted = mean_shift_shift(points, 200.0);
-  return mean_shift_cluster(shifted);
+/// Density based clustering, see `dbscan`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dbscan {
+  /// Radius of the neighbourhood of a point.
+  pub eps: f32,
+
+  /// Other points a neighbourhood must hold for its point to grow a cluster.
+  pub min_pts: usize,
 }
 
-// https://stackoverflow.com/questions/39638363/how-can-i-use-a-hashmap-with-f64-as-key-in-rust
-#[derive(Debug)]
-struct HashPoint(f32, f32);
-impl HashPoint {
-  fn key(&self) -> (u32, u32) {
-    unsafe { (std::mem::transmute(self.0), std::mem::transmute(self.1)) }
+impl Clusterer for Dbscan {
+  fn cluster(&self, points: &[(f32, f32)]) -> Clustering {
+    let labels = dbscan(points, self.eps, self.min_pts)
+      .iter()
+      .map(|label| label.checked_sub(1))
+      .collect();
+    return Clustering::from_labels(points, labels);
   }
 }
-impl std::hash::Hash for HashPoint {
-  fn hash<H>(&self, state: &mut H)
-  where
-    H: std::hash::Hasher,
-  {
-    self.key().hash(state)
-  }
+
+/// Lloyd's algorithm for a fixed number of clusters. The first centroid is the point closest
+/// to the mean of all points and every further one the point furthest from those chosen so
+/// far, so results are deterministic. K-means never reports noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KMeans {
+  pub k: usize,
+
+  /// Upper bound on the number of assignment and update rounds.
+  pub max_iterations: usize,
 }
-impl PartialEq for HashPoint {
-  fn eq(&self, other: &HashPoint) -> bool {
-    self.key() == other.key()
+
+impl KMeans {
+  pub fn new(k: usize) -> Self {
+    KMeans {
+      k,
+      max_iterations: 100,
+    }
   }
-}
-impl Eq for HashPoint {}
 
-fn dbscan_range_query(points: &Vec<(f32, f32)>, point: (f32, f32), eps: f32) -> Vec<(f32, f32)> {
-  let mut neighbors = Vec::new();
-  for other_point in points.iter() {
-    if point != *other_point && dist(point, *other_point) <= eps {
-      neighbors.push(*other_point);
+  fn initial_centroids(&self, points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    let count = points.len() as f32;
+    let mean = (
+      points.iter().map(|p| p.0).sum::<f32>() / count,
+      points.iter().map(|p| p.1).sum::<f32>() / count,
+    );
+    let nearest_distance = |point: &(f32, f32), centroids: &Vec<(f32, f32)>| {
+      centroids
+        .iter()
+        .map(|c| dist(*c, *point))
+        .fold(f32::INFINITY, f32::min)
+    };
+
+    let mut centroids = vec![points[highest_scoring(points, |p| -dist(mean, *p))]];
+    while centroids.len() < self.k.min(points.len()) {
+      let next = highest_scoring(points, |p| nearest_distance(p, &centroids));
+      centroids.push(points[next]);
     }
+    return centroids;
   }
-  return neighbors;
 }
 
-// https://allalgorithms.com/docs/dbscan
-pub fn dbscan(points: &Vec<(f32, f32)>, eps: f32, min_pts: usize) -> Vec<usize> {
-  let mut cluster_index = 0;
-  let mut cluster_assignments: HashMap<HashPoint, usize> = HashMap::new();
-
-  for i in 0..points.len() {
-    let point = points[i];
-    if cluster_assignments.contains_key(&HashPoint(point.0, point.1)) {
-      continue;
+// index of the point that scores highest
+fn highest_scoring<F: Fn(&(f32, f32)) -> f32>(points: &[(f32, f32)], score: F) -> usize {
+  let mut best = 0;
+  for index in 1..points.len() {
+    if score(&points[index]) > score(&points[best]) {
+      best = index;
     }
+  }
+  return best;
+}
 
-    let mut neighbors = dbscan_range_query(points, points[i], eps);
-    if neighbors.len() < min_pts {
-      cluster_assignments.insert(HashPoint(point.0, point.1), 0);
-      continue;
+impl Clusterer for KMeans {
+  fn cluster(&self, points: &[(f32, f32)]) -> Clustering {
+    if points.is_empty() || self.k == 0 {
+      return Clustering::from_labels(points, vec![None; points.len()]);
     }
 
-    cluster_index += 1;
-    cluster_assignments.insert(HashPoint(point.0, point.1), cluster_index);
-
-    while neighbors.len() > 0 {
-      if let Some(neighbor) = neighbors.pop() {
-        if let Some(index) = cluster_assignments.get_mut(&HashPoint(neighbor.0, neighbor.1)) {
-          if *index == 0 {
-            *index = cluster_index;
-          } else {
-            continue;
-          }
+    let mut centroids = self.initial_centroids(points);
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    for _ in 0..self.max_iterations {
+      let mut changed = false;
+      for (index, point) in points.iter().enumerate() {
+        let nearest = highest_scoring(&centroids, |c| -dist(*c, *point));
+        if labels[index] != Some(nearest) {
+          labels[index] = Some(nearest);
+          changed = true;
         }
-
-        cluster_assignments.insert(HashPoint(neighbor.0, neighbor.1), cluster_index);
-
-        let neighbor_neighbors = dbscan_range_query(points, neighbor, eps);
-        if neighbor_neighbors.len() >= min_pts {
-          for nn in neighbor_neighbors.iter() {
-            if !cluster_assignments.contains_key(&HashPoint(nn.0, nn.1)) {
-              neighbors.push(*nn);
-            }
-          }
+      }
+      if !changed {
+        break;
+      }
+      let clustering = Clustering::from_labels(points, labels.clone());
+      // a centroid that lost all of its points keeps its place
+      for (cluster, centroid) in clustering.centroids.iter().enumerate() {
+        if !clustering.members(cluster).is_empty() {
+          centroids[cluster] = *centroid;
         }
       }
     }
+
+    let mut clustering = Clustering::from_labels(points, labels);
+    clustering.centroids = centroids;
+    return clustering;
   }
+}
+
+#[cfg(test)]
+const BLOB_CENTRES: [(f32, f32); 3] = [(50.0, 60.0), (300.0, 80.0), (180.0, 320.0)];
 
-  let mut result = Vec::new();
-  for p in points.iter() {
-    if let Some(ci) = cluster_assignments.get(&HashPoint(p.0, p.1)) {
-      result.push(*ci)
-    } else {
-      result.push(0);
+#[cfg(test)]
+fn three_blobs() -> Vec<(f32, f32)> {
+  let mut points = Vec::new();
+  for (cx, cy) in BLOB_CENTRES.iter() {
+    for i in 0..12 {
+      let angle = i as f32 * std::f32::consts::PI / 6.0;
+      let radius = 4.0 + (i % 3) as f32 * 3.0;
+      points.push((cx + radius * angle.cos(), cy + radius * angle.sin()));
     }
   }
-  return result;
+  return points;
+}
+
+#[test]
+fn should_find_mean_shift_modes() {
+  let points = three_blobs();
+  let mean_shift = MeanShift {
+    bandwidth: 30.0,
+    merge_radius: 15.0,
+    ..MeanShift::default()
+  };
+
+  let result = mean_shift.cluster(&points);
+  assert_eq!(result.len(), 3);
+  assert_eq!(result.labels.len(), points.len());
+  assert!(result.noise.is_empty());
+  for (index, centre) in BLOB_CENTRES.iter().enumerate() {
+    assert!(dist(result.centroids[index], *centre) < 1.0);
+    assert_eq!(
+      result.members(index),
+      (index * 12..(index + 1) * 12).collect::<Vec<_>>()
+    );
+  }
+
+  assert!(mean_shift.cluster(&[]).is_empty());
+}
+
+#[test]
+fn should_cluster_duplicate_points_with_dbscan() {
+  let mut points = vec![(10.0, 10.0); 6];
+  points.extend(vec![
+    (100.0, 100.0),
+    (101.0, 100.0),
+    (100.0, 101.0),
+    (101.0, 101.0),
+  ]);
+  points.push((300.0, 300.0));
+  points.push((10.0, 14.0));
+
+  let labels = dbscan(&points, 5.0, 3);
+  assert!(labels[..6].iter().all(|l| *l == 1));
+  assert!(labels[6..10].iter().all(|l| *l == 2));
+  assert_eq!(labels[10], 0);
+  // a border point joins the cluster it touches
+  assert_eq!(labels[11], 1);
+
+  let clustering = Dbscan {
+    eps: 5.0,
+    min_pts: 3,
+  }
+  .cluster(&points);
+  assert_eq!(clustering.len(), 2);
+  assert_eq!(clustering.noise, vec![10]);
+  assert_eq!(clustering.labels[11], Some(0));
+  assert_eq!(clustering.centroids[1], (100.5, 100.5));
+}
+
+#[test]
+fn should_cluster_with_k_means() {
+  let points = three_blobs();
+  let result = KMeans::new(3).cluster(&points);
+  assert_eq!(result.len(), 3);
+  for (index, centre) in BLOB_CENTRES.iter().enumerate() {
+    let cluster = result.labels[index * 12].unwrap();
+    assert!(dist(result.centroids[cluster], *centre) < 1.0);
+    assert_eq!(result.members(cluster).len(), 12);
+  }
+  assert!(KMeans::new(3).cluster(&[]).is_empty());
 }
@@ -1,4 +1,4 @@
-fn dot(left: &Vec<f32>, right: &Vec<f32>) -> f32 {
+fn dot(left: &[f32], right: &[f32]) -> f32 {
   let mut result = 0.0;
   for i in 0..left.len() {
     result += left[i] * right[i];
@@ -6,6 +6,8 @@ fn dot(left: &Vec<f32>, right: &Vec<f32>) -> f32 {
   return result;
 }
 
+// the published polynomial coefficients, kept exactly as given
+#[allow(clippy::excessive_precision)]
 pub fn turbo(unconstrained_x: f32) -> [u8; 3] {
   let red4 = vec![0.13572138, 4.61539260, -42.66032258, 132.13108234];
   let green4 = vec![0.09140261, 2.19418839, 4.84296658, -14.18503333];
@@ -14,7 +16,7 @@ pub fn turbo(unconstrained_x: f32) -> [u8; 3] {
   let green2 = vec![4.27729857, 2.82956604];
   let blue2 = vec![-89.90310912, 27.34824973];
 
-  let x = unconstrained_x.min(1.0).max(0.0);
+  let x = unconstrained_x.clamp(0.0, 1.0);
   let v4 = vec![1.0, x, x * x, x * x * x];
   let v2 = vec![v4[2] * v4[2], v4[3] * v4[2]];
   return [
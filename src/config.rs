@@ -0,0 +1,83 @@
+use crate::corners::CornerParams;
+use crate::error::SegmentError;
+use crate::line::{AdaptiveHough, Hough, Line, LineDetector};
+use crate::lsd::Lsd;
+use image::GrayImage;
+
+/// Where `segment_with_config` looks for square corners.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CornerSource {
+  /// Intersections of the lines found by `SegmentConfig::lines` that look like corners.
+  /// Cheap, but a row of corners is lost whenever its line is missed.
+  Lines,
+
+  /// Every corner found by scanning the whole image, see `detect_corners`.
+  Dense(CornerParams),
+}
+
+/// How `segment_with_config` finds lines when it looks for corners along them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LineSource {
+  /// Whole-image lines at whole degree angles, see `Hough`.
+  Hough(Hough),
+
+  /// Like `Hough`, with thresholds chosen for each image, see `AdaptiveHough`.
+  AdaptiveHough(AdaptiveHough),
+
+  /// Line segments at any angle, see `Lsd`. Follows boards viewed at odd angles more closely.
+  Lsd(Lsd),
+}
+
+impl LineDetector for LineSource {
+  fn detect(&self, i: &GrayImage) -> Result<Vec<Line>, SegmentError> {
+    match self {
+      LineSource::Hough(hough) => hough.detect(i),
+      LineSource::AdaptiveHough(hough) => hough.detect(i),
+      LineSource::Lsd(lsd) => lsd.detect(i),
+    }
+  }
+}
+
+/// How `segment_with_config` locates the board among the square corners.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoardFit {
+  /// The corners of the smallest rectangle around the corners, moved onto the nearest
+  /// candidates. The projection is fitted to those four points alone, so an error in any of
+  /// them skews the whole board.
+  BoundingBox,
+
+  /// The square grid that best explains the corners, see `fit_grid`. Slower, but unaffected
+  /// by stray corners around the board, and the projection is fitted with `Ransac` to every
+  /// lattice corner that was found.
+  Grid,
+}
+
+/// Choices made at each stage of `segment_with_config`. The default is what `segment` uses,
+/// which fits the board to four corners with `BoardFit::BoundingBox` rather than to all of the
+/// lattice corners; use `BoardFit::Grid` for that.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentConfig {
+  pub corners: CornerSource,
+  pub lines: LineSource,
+  pub board: BoardFit,
+}
+
+impl Default for SegmentConfig {
+  fn default() -> Self {
+    SegmentConfig {
+      corners: CornerSource::Lines,
+      lines: LineSource::Hough(Hough::default()),
+      board: BoardFit::BoundingBox,
+    }
+  }
+}
+
+impl SegmentConfig {
+  /// Detect corners by scanning the whole image with the default `CornerParams`.
+  pub fn dense() -> Self {
+    SegmentConfig {
+      corners: CornerSource::Dense(CornerParams::default()),
+      ..SegmentConfig::default()
+    }
+  }
+}
@@ -0,0 +1,324 @@
+// Finds the points where four squares meet anywhere in an image, without relying on lines.
+// The response is the ChESS detector of Bennett and Lasenby: around a square corner the pixels
+// on a circle alternate dark and light every quarter turn, so opposite samples agree and
+// samples a quarter turn apart differ. It is evaluated on circles of several radii so both
+// small and large boards are found.
+
+use crate::cluster::dbscan;
+use crate::lattice::{bilinear, refine_corner};
+use image::GrayImage;
+use imageproc::filter::gaussian_blur_f32;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// samples taken on every circle
+const RING_SAMPLES: usize = 16;
+
+/// Parameters of `detect_corners`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CornerParams {
+  /// Radii in pixels of the circles sampled around every pixel. The image is blurred in
+  /// proportion to each radius first, so large radii also tolerate out of focus boards.
+  pub radii: Vec<f32>,
+
+  /// Lowest response kept. An ideal corner between squares whose intensities differ by `c`
+  /// responds with `8 * c`.
+  pub threshold: f32,
+
+  /// Only the strongest corner within this many pixels is kept.
+  pub nms_radius: u32,
+
+  /// Whether corners are moved onto the sub-pixel saddle point of the intensity.
+  pub refine: bool,
+}
+
+impl Default for CornerParams {
+  fn default() -> Self {
+    CornerParams {
+      radii: vec![3.0, 5.0, 8.0],
+      threshold: 128.0,
+      nms_radius: 4,
+      refine: true,
+    }
+  }
+}
+
+/// A square corner found by `detect_corners`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Corner {
+  pub pos: (f32, f32),
+
+  /// Response at the best scale, higher is more corner like.
+  pub score: f32,
+
+  /// Radius of the circle that responded most strongly.
+  pub scale: f32,
+
+  /// Direction in radians, in `[0, PI)`, from the corner into a light square, halfway between
+  /// the two edges that cross there.
+  pub angle: f32,
+}
+
+// the response on a circle of `radius` around `(x, y)`, and the phase of the light squares
+fn chess_response(i: &GrayImage, x: f32, y: f32, radius: f32) -> (f32, f32) {
+  let mut ring = [0.0; RING_SAMPLES];
+  for (n, sample) in ring.iter_mut().enumerate() {
+    let theta = 2.0 * PI * n as f32 / RING_SAMPLES as f32;
+    *sample = bilinear(i, x + radius * theta.cos(), y + radius * theta.sin());
+  }
+
+  let quarter = RING_SAMPLES / 4;
+  let half = RING_SAMPLES / 2;
+  let mut sum_response = 0.0;
+  for n in 0..quarter {
+    sum_response += (ring[n] + ring[n + half] - ring[n + quarter] - ring[n + half + quarter]).abs();
+  }
+  // an edge through the centre makes opposite samples differ
+  let mut diff_response = 0.0;
+  for n in 0..half {
+    diff_response += (ring[n] - ring[n + half]).abs();
+  }
+  // a blob or line end makes the centre differ from its surroundings
+  let centre = (bilinear(i, x, y)
+    + bilinear(i, x - 1.0, y)
+    + bilinear(i, x + 1.0, y)
+    + bilinear(i, x, y - 1.0)
+    + bilinear(i, x, y + 1.0))
+    / 5.0;
+  let ring_mean = ring.iter().sum::<f32>() / RING_SAMPLES as f32;
+  let mean_response = (ring_mean - centre).abs();
+
+  let (mut cos_sum, mut sin_sum) = (0.0, 0.0);
+  for (n, sample) in ring.iter().enumerate() {
+    let theta = 2.0 * PI * n as f32 / RING_SAMPLES as f32;
+    cos_sum += sample * (2.0 * theta).cos();
+    sin_sum += sample * (2.0 * theta).sin();
+  }
+  let angle = (sin_sum.atan2(cos_sum) / 2.0).rem_euclid(PI);
+
+  return (
+    sum_response - diff_response - RING_SAMPLES as f32 * mean_response,
+    angle,
+  );
+}
+
+/// Finds square corners anywhere in `i`, strongest first.
+///
+/// Every pixel is scored on each of `params.radii` and keeps its best scale, then corners
+/// below `params.threshold` or weaker than another corner within `params.nms_radius` are
+/// dropped.
+pub fn detect_corners(i: &GrayImage, params: &CornerParams) -> Vec<Corner> {
+  let (width, height) = i.dimensions();
+  let mut best: Vec<Option<Corner>> = vec![None; (width * height) as usize];
+  for radius in params.radii.iter() {
+    let blurred = gaussian_blur_f32(i, (radius / 5.0).max(0.5));
+    // the circle and the pixel after it must stay inside the image
+    let margin = radius.ceil() as u32 + 1;
+    if width <= 2 * margin || height <= 2 * margin {
+      continue;
+    }
+    for y in margin..height - margin {
+      for x in margin..width - margin {
+        let (score, angle) = chess_response(&blurred, x as f32, y as f32, *radius);
+        let index = (y * width + x) as usize;
+        if score > params.threshold && best[index].is_none_or(|corner| score > corner.score) {
+          best[index] = Some(Corner {
+            pos: (x as f32, y as f32),
+            score,
+            scale: *radius,
+            angle,
+          });
+        }
+      }
+    }
+  }
+
+  let reach = params.nms_radius as i64;
+  let at = |x: i64, y: i64| -> Option<Corner> {
+    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+      return None;
+    }
+    return best[(y * width as i64 + x) as usize];
+  };
+  let mut corners = Vec::new();
+  for (index, corner) in best.iter().enumerate() {
+    let corner = match corner {
+      Some(corner) => *corner,
+      None => continue,
+    };
+    let (x, y) = ((index as u32 % width) as i64, (index as u32 / width) as i64);
+    // ties go to the first pixel in scan order
+    let is_maximum = (y - reach..=y + reach).all(|ny| {
+      (x - reach..=x + reach).all(|nx| {
+        let earlier = (ny, nx) < (y, x);
+        at(nx, ny).is_none_or(|other| {
+          other.score < corner.score || (other.score == corner.score && !earlier)
+        })
+      })
+    });
+    if is_maximum {
+      corners.push(corner);
+    }
+  }
+
+  if params.refine {
+    for corner in corners.iter_mut() {
+      corner.pos = refine_corner(i, corner.pos).unwrap_or(corner.pos);
+    }
+  }
+  corners.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+  return corners;
+}
+
+// corners whose diagonals deviate further than this from the most common one are not part of
+// the board, nor are neighbours in a direction further than this from its edges
+const MAX_ANGLE_DEVIATION: f32 = 0.35;
+
+// half the width of the window the most common diagonal is picked with
+const DOMINANT_ANGLE_WINDOW: f32 = 0.1;
+
+// neighbours within this fraction of the square size of where the lattice predicts them
+const SPACING_TOLERANCE: f32 = 0.35;
+
+fn distance(p: (f32, f32), q: (f32, f32)) -> f32 {
+  return ((p.0 - q.0).powf(2.0) + (p.1 - q.1).powf(2.0)).sqrt();
+}
+
+// how far apart two directions are when a turn of `period` is no difference at all
+fn angle_difference(a: f32, b: f32, period: f32) -> f32 {
+  let difference = (a - b).rem_euclid(period);
+  return difference.min(period - difference);
+}
+
+/// The largest group of `corners` that is spaced and oriented like the lattice of a board,
+/// which drops the corners found within pieces and around the board.
+pub fn lattice_corners(corners: &[Corner]) -> Vec<Corner> {
+  // the two diagonals of every corner are a quarter turn apart, and all corners of a board seen
+  // at a moderate angle share them. Weighted by score, as the many faint corners within pieces
+  // point every which way, and counted in a narrow window so they cannot pull it off the board
+  let aligned_with = |angle: f32| -> f32 {
+    corners
+      .iter()
+      .filter(|other| angle_difference(angle, other.angle, PI / 2.0) <= DOMINANT_ANGLE_WINDOW)
+      .map(|other| other.score)
+      .sum()
+  };
+  let dominant = match corners
+    .iter()
+    .map(|corner| (corner.angle, aligned_with(corner.angle)))
+    .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+  {
+    Some((angle, _)) => angle,
+    None => return Vec::new(),
+  };
+  let corners: Vec<Corner> = corners
+    .iter()
+    .filter(|corner| angle_difference(dominant, corner.angle, PI / 2.0) <= MAX_ANGLE_DEVIATION)
+    .copied()
+    .collect();
+
+  // the nearest corner along an edge of `corner` where light and dark squares are swapped, as
+  // they are at the next corner of the lattice
+  let along_edge = |corner: &Corner, edge: f32| -> Option<f32> {
+    return corners
+      .iter()
+      .filter(|other| {
+        let direction = (other.pos.1 - corner.pos.1).atan2(other.pos.0 - corner.pos.0);
+        other.pos != corner.pos
+          && angle_difference(direction, edge, PI) <= MAX_ANGLE_DEVIATION
+          && angle_difference(other.angle, corner.angle + PI / 2.0, PI) <= MAX_ANGLE_DEVIATION
+      })
+      .map(|other| distance(corner.pos, other.pos))
+      .min_by(|a, b| a.partial_cmp(b).unwrap());
+  };
+  let edges = [PI / 4.0, -PI / 4.0];
+  let steps: Vec<[Option<f32>; 2]> = corners
+    .iter()
+    .map(|corner| {
+      [
+        along_edge(corner, dominant + edges[0]),
+        along_edge(corner, dominant + edges[1]),
+      ]
+    })
+    .collect();
+
+  // squares are only the same size along both edges when the image was not stretched, so each
+  // edge gets its own spacing
+  let mut spacings = [0.0; 2];
+  for edge in 0..2 {
+    let mut found: Vec<f32> = steps.iter().filter_map(|step| step[edge]).collect();
+    if found.is_empty() {
+      return Vec::new();
+    }
+    found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    spacings[edge] = found[found.len() / 2];
+  }
+
+  // every corner of the lattice has a neighbour about a square away along both of its edges
+  let corners: Vec<Corner> = corners
+    .iter()
+    .zip(steps.iter())
+    .filter(|(_, step)| {
+      (0..2).all(|edge| {
+        step[edge].is_some_and(|d| (d - spacings[edge]).abs() < spacings[edge] * SPACING_TOLERANCE)
+      })
+    })
+    .map(|(corner, _)| *corner)
+    .collect();
+
+  let points: Vec<(f32, f32)> = corners.iter().map(|corner| corner.pos).collect();
+  let spacing = spacings[0].max(spacings[1]);
+  let labels = dbscan(&points, spacing * (1.0 + SPACING_TOLERANCE), 2);
+  let mut sizes: HashMap<usize, usize> = HashMap::new();
+  for label in labels.iter().filter(|label| **label != 0) {
+    *sizes.entry(*label).or_insert(0) += 1;
+  }
+  let board = sizes
+    .iter()
+    .max_by_key(|(label, size)| (**size, Reverse(**label)))
+    .map(|(label, _)| *label);
+  return corners
+    .iter()
+    .zip(labels.iter())
+    .filter(|(_, label)| Some(**label) == board)
+    .map(|(corner, _)| *corner)
+    .collect();
+}
+
+#[test]
+fn should_detect_corners_at_every_scale_and_rotation() {
+  use crate::checkerboard::Checkerboard;
+
+  for (square, angle) in [(12.0, 0.0), (25.0, 0.4), (45.0, 1.1)].iter() {
+    let origin = (119.3, 121.6);
+    let image = Checkerboard {
+      samples: 4,
+      ..Checkerboard::rotated(4, *square, *angle, origin)
+    }
+    .gray(240, 240);
+    let corners = detect_corners(&image, &CornerParams::default());
+
+    // the 3x3 inner corners of the board, where the light squares lie along alternating
+    // diagonals
+    let (sin, cos) = angle.sin_cos();
+    for row in -1..=1 {
+      for column in -1..=1 {
+        let (u, v) = (column as f32 * square, row as f32 * square);
+        let truth = (origin.0 + u * cos - v * sin, origin.1 + u * sin + v * cos);
+        let found = corners
+          .iter()
+          .find(|c| ((c.pos.0 - truth.0).powf(2.0) + (c.pos.1 - truth.1).powf(2.0)).sqrt() < 0.5)
+          .unwrap_or_else(|| panic!("no corner at {:?} of a {} board", truth, square));
+
+        let diagonal = if (row + column) % 2 == 0 { 0.25 } else { 0.75 };
+        let off = (found.angle - angle - diagonal * PI).rem_euclid(PI);
+        assert!(off.min(PI - off) < 0.1, "{:?}", found);
+      }
+    }
+    assert_eq!(corners.len(), 9, "{:?}", corners);
+  }
+
+  let blank = GrayImage::from_pixel(60, 60, image::Luma([128]));
+  assert!(detect_corners(&blank, &CornerParams::default()).is_empty());
+}
@@ -1,43 +1,39 @@
+use crate::error::SegmentError;
 use image::{GrayImage, RgbImage, RgbaImage};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub fn debug_images() -> bool {
   return std::env::var("DEBUG").is_ok();
 }
 
-pub fn write_gray(i: &GrayImage, name: &str) {
-  if debug_images() {
-    let output_dir = Path::new("./tmp");
-    if !output_dir.is_dir() {
-      fs::create_dir(output_dir).expect("Failed to create output directory")
-    }
-
-    let path = output_dir.join(format!("{}.png", name));
-    i.save(path).unwrap();
+fn output_path(name: &str) -> Result<PathBuf, SegmentError> {
+  let output_dir = Path::new("./tmp");
+  if !output_dir.is_dir() {
+    fs::create_dir(output_dir)?;
   }
+
+  return Ok(output_dir.join(format!("{}.png", name)));
 }
 
-pub fn write_rgb(i: &RgbImage, name: &str) {
+pub fn write_gray(i: &GrayImage, name: &str) -> Result<(), SegmentError> {
   if debug_images() {
-    let output_dir = Path::new("./tmp");
-    if !output_dir.is_dir() {
-      fs::create_dir(output_dir).expect("Failed to create output directory")
-    }
-
-    let path = output_dir.join(format!("{}.png", name));
-    i.save(path).unwrap();
+    i.save(output_path(name)?)?;
   }
+  return Ok(());
 }
 
-pub fn write_rgba(i: &RgbaImage, name: &str) {
+pub fn write_rgb(i: &RgbImage, name: &str) -> Result<(), SegmentError> {
   if debug_images() {
-    let output_dir = Path::new("./tmp");
-    if !output_dir.is_dir() {
-      fs::create_dir(output_dir).expect("Failed to create output directory")
-    }
+    i.save(output_path(name)?)?;
+  }
+  return Ok(());
+}
 
-    let path = output_dir.join(format!("{}.png", name));
-    i.save(path).unwrap();
+#[allow(dead_code)]
+pub fn write_rgba(i: &RgbaImage, name: &str) -> Result<(), SegmentError> {
+  if debug_images() {
+    i.save(output_path(name)?)?;
   }
+  return Ok(());
 }
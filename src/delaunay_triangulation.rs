@@ -56,7 +56,7 @@ impl Point {
 /// Represents the area outside of the triangulation.
 /// Halfedges on the convex hull (which don't have an adjacent halfedge)
 /// will have this value.
-pub const EMPTY: usize = usize::max_value();
+pub const EMPTY: usize = usize::MAX;
 
 /// Next halfedge in a triangle.
 pub fn next_halfedge(i: usize) -> usize {
@@ -173,7 +173,7 @@ impl Triangulation {
     let pl = self.triangles[al];
     let p1 = self.triangles[bl];
 
-    let illegal = (&points[p0]).in_circle(&points[pr], &points[pl], &points[p1]);
+    let illegal = points[p0].in_circle(&points[pr], &points[pl], &points[p1]);
     if illegal {
       self.triangles[a] = p1;
       self.triangles[b] = p0;
@@ -375,7 +375,7 @@ pub fn triangulate(points: &[Point]) -> Option<Triangulation> {
   let n = points.len();
 
   let (i0, i1, i2) = find_seed_triangle(points)?;
-  let center = (&points[i0]).circumcenter(&points[i1], &points[i2]);
+  let center = points[i0].circumcenter(&points[i1], &points[i2]);
 
   let mut triangulation = Triangulation::new(n);
   triangulation.add_triangle(i0, i1, i2, EMPTY, EMPTY, EMPTY);
@@ -0,0 +1,73 @@
+use std::error::Error;
+use std::fmt;
+
+/// Reasons a chess board could not be segmented from an image.
+#[derive(Debug)]
+pub enum SegmentError {
+  /// Line detection did not find any lines in the image.
+  NoLinesFound,
+
+  /// The detected lines do not fall into the two directions of the board edges.
+  NoLineFamilies,
+
+  /// None of the detected lines intersect within the image.
+  NoIntersections,
+
+  /// Fewer board corners were detected than are required to locate the board.
+  TooFewCorners(usize),
+
+  /// The detected board covers too little of the image to be trusted.
+  BoardTooSmall,
+
+  /// No square grid could be fitted to the detected corners.
+  NoGridFound,
+
+  /// The board corners do not define an invertible projection.
+  DegenerateHomography,
+
+  /// Writing debug output failed.
+  Io(std::io::Error),
+
+  /// Encoding debug output failed.
+  Image(image::ImageError),
+}
+
+impl fmt::Display for SegmentError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SegmentError::NoLinesFound => write!(f, "no lines found in image"),
+      SegmentError::NoLineFamilies => write!(f, "lines do not fall into two directions"),
+      SegmentError::NoIntersections => write!(f, "no line intersections found in image"),
+      SegmentError::TooFewCorners(found) => {
+        write!(f, "found {} board corners, at least 4 are required", found)
+      }
+      SegmentError::BoardTooSmall => write!(f, "detected board is too small"),
+      SegmentError::NoGridFound => write!(f, "no square grid found among board corners"),
+      SegmentError::DegenerateHomography => write!(f, "could not compute projection matrix"),
+      SegmentError::Io(err) => write!(f, "io error: {}", err),
+      SegmentError::Image(err) => write!(f, "image error: {}", err),
+    }
+  }
+}
+
+impl Error for SegmentError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      SegmentError::Io(err) => Some(err),
+      SegmentError::Image(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for SegmentError {
+  fn from(err: std::io::Error) -> Self {
+    SegmentError::Io(err)
+  }
+}
+
+impl From<image::ImageError> for SegmentError {
+  fn from(err: image::ImageError) -> Self {
+    SegmentError::Image(err)
+  }
+}
@@ -0,0 +1,288 @@
+// Fits the projection between two planes to any number of matched points, optionally
+// ignoring the ones that do not agree with the rest.
+
+use imageproc::geometric_transformations::Projection;
+
+const DEFAULT_RANSAC_THRESHOLD: f32 = 2.0;
+const DEFAULT_RANSAC_ITERATIONS: usize = 200;
+
+/// Least squares projection mapping every `from` point onto the matching `to` point, using the
+/// direct linear transform on coordinates normalised around their centroid. Unlike
+/// `Projection::from_control_points` it stays well conditioned for quads measured to a
+/// fraction of a pixel.
+#[allow(clippy::needless_range_loop)]
+pub fn fit_projection(from: &[(f32, f32)], to: &[(f32, f32)]) -> Option<Projection> {
+  if from.len() < 4 || from.len() != to.len() {
+    return None;
+  }
+
+  let normalisation = |points: &[(f32, f32)]| {
+    let count = points.len() as f32;
+    let cx = points.iter().map(|p| p.0).sum::<f32>() / count;
+    let cy = points.iter().map(|p| p.1).sum::<f32>() / count;
+    let spread = points
+      .iter()
+      .map(|p| ((p.0 - cx).powf(2.0) + (p.1 - cy).powf(2.0)).sqrt())
+      .sum::<f32>()
+      / count;
+    let scale = if spread > 0.0 {
+      2f32.sqrt() / spread
+    } else {
+      1.0
+    };
+    Projection::scale(scale, scale) * Projection::translate(-cx, -cy)
+  };
+  let from_normalisation = normalisation(from);
+  let to_normalisation = normalisation(to);
+
+  // normal equations of the 8 unknown entries, the last one is fixed at 1
+  let mut a = [[0f64; 9]; 8];
+  for (p, q) in from.iter().zip(to.iter()) {
+    let (x, y) = from_normalisation * *p;
+    let (u, v) = to_normalisation * *q;
+    let (x, y, u, v) = (x as f64, y as f64, u as f64, v as f64);
+    let rows = [
+      [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u],
+      [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v],
+    ];
+    for row in rows.iter() {
+      for i in 0..8 {
+        for j in 0..9 {
+          a[i][j] += row[i] * row[j];
+        }
+      }
+    }
+  }
+
+  // gaussian elimination with partial pivoting
+  for column in 0..8 {
+    let magnitude = |row: &usize| a[*row][column].abs();
+    let pivot = (column..8).max_by(|i, j| magnitude(i).partial_cmp(&magnitude(j)).unwrap())?;
+    if a[pivot][column].abs() < 1e-12 {
+      return None;
+    }
+    a.swap(column, pivot);
+    for row in 0..8 {
+      if row != column {
+        let factor = a[row][column] / a[column][column];
+        for k in column..9 {
+          a[row][k] -= factor * a[column][k];
+        }
+      }
+    }
+  }
+  let mut matrix = [1f32; 9];
+  for i in 0..8 {
+    matrix[i] = (a[i][8] / a[i][i]) as f32;
+  }
+
+  let normalised = Projection::from_matrix(matrix)?;
+  return Some(to_normalisation.invert() * normalised * from_normalisation);
+}
+
+/// Distance from where `projection` puts each `from` point to the matching `to` point.
+pub fn reprojection_errors(
+  projection: &Projection,
+  from: &[(f32, f32)],
+  to: &[(f32, f32)],
+) -> Vec<f32> {
+  return from
+    .iter()
+    .zip(to.iter())
+    .map(|(p, q)| {
+      let (x, y) = *projection * *p;
+      ((x - q.0).powf(2.0) + (y - q.1).powf(2.0)).sqrt()
+    })
+    .collect();
+}
+
+/// A projection fitted to the matched points that agree with it.
+#[derive(Clone, Debug)]
+pub struct HomographyFit {
+  pub projection: Projection,
+  /// Indices of the correspondences that `projection` maps within the threshold of their
+  /// target.
+  pub inliers: Vec<usize>,
+  /// Reprojection error of every correspondence, inliers or not, in `to` coordinates.
+  pub errors: Vec<f32>,
+}
+
+impl HomographyFit {
+  /// Root mean square reprojection error over the inliers.
+  pub fn rms_error(&self) -> f32 {
+    if self.inliers.is_empty() {
+      return 0.0;
+    }
+    let sum: f32 = self.inliers.iter().map(|i| self.errors[*i].powf(2.0)).sum();
+    return (sum / self.inliers.len() as f32).sqrt();
+  }
+}
+
+/// Random sample consensus over `fit_projection`.
+///
+/// Projections are fitted to `iterations` random sets of four correspondences and the one
+/// that puts the most `from` points within `threshold` of their `to` point is refitted to all
+/// of those. Samples are drawn from a fixed seed, so the same points always give the same fit.
+#[derive(Clone, Debug)]
+pub struct Ransac {
+  pub threshold: f32,
+  pub iterations: usize,
+}
+
+impl Default for Ransac {
+  fn default() -> Self {
+    Ransac {
+      threshold: DEFAULT_RANSAC_THRESHOLD,
+      iterations: DEFAULT_RANSAC_ITERATIONS,
+    }
+  }
+}
+
+impl Ransac {
+  /// `None` when fewer than four correspondences agree on any projection.
+  pub fn fit(&self, from: &[(f32, f32)], to: &[(f32, f32)]) -> Option<HomographyFit> {
+    if from.len() < 4 || from.len() != to.len() {
+      return None;
+    }
+
+    let agreeing = |projection: &Projection| -> (Vec<usize>, f32) {
+      let errors = reprojection_errors(projection, from, to);
+      let inliers: Vec<usize> = (0..errors.len())
+        .filter(|i| errors[*i] <= self.threshold)
+        .collect();
+      let total = inliers.iter().map(|i| errors[*i]).sum();
+      (inliers, total)
+    };
+
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = |bound: usize| {
+      // xorshift
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      (state % bound as u64) as usize
+    };
+
+    let mut best: Option<(Vec<usize>, f32)> = None;
+    for _ in 0..self.iterations {
+      let mut sample: Vec<usize> = Vec::with_capacity(4);
+      while sample.len() < 4 {
+        let index = random(from.len());
+        if !sample.contains(&index) {
+          sample.push(index);
+        }
+      }
+      let sample_from: Vec<(f32, f32)> = sample.iter().map(|i| from[*i]).collect();
+      let sample_to: Vec<(f32, f32)> = sample.iter().map(|i| to[*i]).collect();
+      let projection = match fit_projection(&sample_from, &sample_to) {
+        Some(projection) => projection,
+        None => continue,
+      };
+      let (inliers, total) = agreeing(&projection);
+      let better = best.as_ref().is_none_or(|(best_inliers, best_total)| {
+        inliers.len() > best_inliers.len()
+          || (inliers.len() == best_inliers.len() && total < *best_total)
+      });
+      if better {
+        let everything = inliers.len() == from.len();
+        best = Some((inliers, total));
+        if everything {
+          break;
+        }
+      }
+    }
+
+    // the minimal sample is noisy, so the consensus is refitted until it stops growing
+    let (mut inliers, _) = best?;
+    let mut projection = None;
+    while inliers.len() >= 4 {
+      let inlier_from: Vec<(f32, f32)> = inliers.iter().map(|i| from[*i]).collect();
+      let inlier_to: Vec<(f32, f32)> = inliers.iter().map(|i| to[*i]).collect();
+      let refitted = match fit_projection(&inlier_from, &inlier_to) {
+        Some(refitted) => refitted,
+        None => break,
+      };
+      // the inliers are always the ones of the projection that is kept
+      let (agreeing, _) = agreeing(&refitted);
+      let grew = agreeing.len() > inliers.len();
+      projection = Some(refitted);
+      inliers = agreeing;
+      if !grew {
+        break;
+      }
+    }
+
+    let projection = projection?;
+    return Some(HomographyFit {
+      projection,
+      errors: reprojection_errors(&projection, from, to),
+      inliers,
+    });
+  }
+}
+
+#[test]
+fn should_fit_projection_to_many_points() {
+  let truth = Projection::from_control_points(
+    [(10.0, 20.0), (300.0, 40.0), (280.0, 310.0), (30.0, 290.0)],
+    [(0.0, 0.0), (400.0, 0.0), (400.0, 400.0), (0.0, 400.0)],
+  )
+  .unwrap();
+  let from: Vec<(f32, f32)> = (0..20)
+    .map(|i| (15.0 + (i % 5) as f32 * 60.0, 30.0 + (i / 5) as f32 * 70.0))
+    .collect();
+  let to: Vec<(f32, f32)> = from.iter().map(|p| truth * *p).collect();
+  let fitted = fit_projection(&from, &to).unwrap();
+  for (p, q) in from.iter().zip(to.iter()) {
+    let (x, y) = fitted * *p;
+    assert!((x - q.0).abs() < 0.05 && (y - q.1).abs() < 0.05);
+  }
+}
+
+#[test]
+fn should_ignore_outliers_with_ransac() {
+  let truth = Projection::from_control_points(
+    [(40.0, 30.0), (350.0, 60.0), (330.0, 380.0), (20.0, 350.0)],
+    [(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)],
+  )
+  .unwrap();
+  let inverse = truth.invert();
+  let mut from = Vec::new();
+  let mut to = Vec::new();
+  for row in 0..9 {
+    for column in 0..9 {
+      let q = (column as f32, row as f32);
+      // a little noise on every corner and a few corners on the wrong square
+      let wrong = (row * 9 + column) % 7 == 3;
+      let (x, y) = inverse * if wrong { (q.0 + 0.5, q.1 - 0.5) } else { q };
+      let jitter = ((row * 5 + column * 3) % 5) as f32 * 0.1 - 0.2;
+      from.push((x + jitter, y - jitter));
+      to.push(q);
+    }
+  }
+
+  let plain = fit_projection(&from, &to).unwrap();
+  let robust = Ransac {
+    threshold: 0.1,
+    ..Ransac::default()
+  }
+  .fit(&from, &to)
+  .unwrap();
+  assert_eq!(
+    robust.inliers,
+    (0..81).filter(|i| i % 7 != 3).collect::<Vec<usize>>()
+  );
+  assert!(robust.rms_error() < 0.05);
+  for (index, error) in robust.errors.iter().enumerate() {
+    assert_eq!(robust.inliers.contains(&index), *error <= 0.1);
+  }
+
+  let worst = |projection: &Projection| {
+    let clean: Vec<(f32, f32)> = to.iter().map(|q| inverse * *q).collect();
+    reprojection_errors(projection, &clean, &to)
+      .into_iter()
+      .fold(0.0, f32::max)
+  };
+  assert!(worst(&robust.projection) < 0.05);
+  assert!(worst(&plain) > 2.0 * worst(&robust.projection));
+}
@@ -0,0 +1,228 @@
+// Gives detected corners their place on the board lattice by walking from one known square to
+// its neighbours, which follows a grid bent by perspective or lens distortion far better than
+// extrapolating a single square across the board.
+
+use crate::spatial::GridIndex;
+use std::collections::{HashMap, VecDeque};
+
+// a corner is looked for within this fraction of the local corner spacing of where the walk
+// predicts it
+const SEARCH_FRACTION: f32 = 0.3;
+
+// the walk crosses at most this many missing corners in a row
+const MAX_GAP: usize = 1;
+
+const NEIGHBOURS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// Lattice coordinates of corner points, as found by `index_lattice`.
+#[derive(Clone, Debug)]
+pub struct LatticeIndex {
+  coordinates: Vec<Option<(i32, i32)>>,
+  points: HashMap<(i32, i32), usize>,
+}
+
+impl LatticeIndex {
+  /// Coordinates of every point in the order they were given, `None` for the outliers the walk
+  /// never reached.
+  pub fn coordinates(&self) -> &[Option<(i32, i32)>] {
+    return &self.coordinates;
+  }
+
+  /// Index of the point at `(i, j)`, if one was found there.
+  pub fn point_at(&self, i: i32, j: i32) -> Option<usize> {
+    return self.points.get(&(i, j)).copied();
+  }
+
+  /// Number of points that were given coordinates.
+  pub fn len(&self) -> usize {
+    return self.points.len();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.points.is_empty();
+  }
+
+  /// The squares all four of whose corners were found, by the coordinates of their top left
+  /// corner, in row order.
+  pub fn observed_squares(&self) -> Vec<(i32, i32)> {
+    let mut squares: Vec<(i32, i32)> = self
+      .points
+      .keys()
+      .filter(|(i, j)| {
+        self.points.contains_key(&(i + 1, *j))
+          && self.points.contains_key(&(*i, j + 1))
+          && self.points.contains_key(&(i + 1, j + 1))
+      })
+      .copied()
+      .collect();
+    squares.sort_by_key(|(i, j)| (*j, *i));
+    return squares;
+  }
+}
+
+// a lattice position the walk has reached, found or predicted
+#[derive(Clone, Copy, Debug)]
+struct Step {
+  position: (f32, f32),
+  // image offsets to the next corner along i and along j
+  basis: [(f32, f32); 2],
+  // missing corners on the way here
+  missed: usize,
+}
+
+/// Assigns integer lattice coordinates `(i, j)` to `points` by walking outwards from `seed`,
+/// four corners of one square in clockwise order that get `(0, 0)`, `(1, 0)`, `(1, 1)` and
+/// `(0, 1)`.
+///
+/// Each neighbour of a corner is predicted from the spacing seen so far along the walk and
+/// taken to be the nearest point close enough to the prediction. Single missing corners are
+/// stepped over, points off the lattice are never reached and keep no coordinates.
+pub fn index_lattice(points: &[(f32, f32)], seed: [(f32, f32); 4]) -> LatticeIndex {
+  let mut index = LatticeIndex {
+    coordinates: vec![None; points.len()],
+    points: HashMap::new(),
+  };
+  let difference = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0, a.1 - b.1);
+  let length = |(x, y): (f32, f32)| (x * x + y * y).sqrt();
+  let mean = |a: (f32, f32), b: (f32, f32)| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+  let seed_basis = [
+    mean(difference(seed[1], seed[0]), difference(seed[2], seed[3])),
+    mean(difference(seed[3], seed[0]), difference(seed[2], seed[1])),
+  ];
+  let spacing = length(seed_basis[0]).min(length(seed_basis[1]));
+  if points.is_empty() || spacing <= 0.0 {
+    return index;
+  }
+  let grid = GridIndex::new(points, spacing * SEARCH_FRACTION);
+
+  // the nearest point without coordinates close enough to `expected`, which is given them
+  let claim =
+    |index: &mut LatticeIndex, coordinate: (i32, i32), expected, basis: [(f32, f32); 2]| {
+      let radius = length(basis[0]).min(length(basis[1])) * SEARCH_FRACTION;
+      let distance = |point: &usize| length(difference(points[*point], expected));
+      let nearest = grid
+        .within(expected, radius)
+        .into_iter()
+        .filter(|point| index.coordinates[*point].is_none())
+        .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap())?;
+      index.coordinates[nearest] = Some(coordinate);
+      index.points.insert(coordinate, nearest);
+      return Some(points[nearest]);
+    };
+
+  let mut visited: HashMap<(i32, i32), Step> = HashMap::new();
+  let mut queue: VecDeque<(i32, i32)> = VecDeque::new();
+  for (corner, coordinate) in seed.iter().zip([(0, 0), (1, 0), (1, 1), (0, 1)].iter()) {
+    if let Some(found) = claim(&mut index, *coordinate, *corner, seed_basis) {
+      let step = Step {
+        position: found,
+        basis: seed_basis,
+        missed: 0,
+      };
+      visited.insert(*coordinate, step);
+      queue.push_back(*coordinate);
+    }
+  }
+
+  while let Some((i, j)) = queue.pop_front() {
+    let step = visited[&(i, j)];
+    for (di, dj) in NEIGHBOURS.iter() {
+      let neighbour = (i + di, j + dj);
+      if visited.contains_key(&neighbour) {
+        continue;
+      }
+      let axis = if *di != 0 { 0 } else { 1 };
+      let sign = (di + dj) as f32;
+      let across = if axis == 0 { (0, 1) } else { (1, 0) };
+      let found_at =
+        |index: &LatticeIndex, (x, y): (i32, i32)| index.point_at(x, y).map(|point| points[point]);
+
+      // next to a row already walked the square is completed from its other three corners,
+      // which follows the local shape of the lattice more closely than the spacing alone
+      let completed = [1, -1].iter().find_map(|side| {
+        let beside = (neighbour.0 + across.0 * side, neighbour.1 + across.1 * side);
+        let corner = (i + across.0 * side, j + across.1 * side);
+        let offset = difference(found_at(&index, beside)?, found_at(&index, corner)?);
+        Some((step.position.0 + offset.0, step.position.1 + offset.1))
+      });
+      let expected = completed.unwrap_or((
+        step.position.0 + step.basis[axis].0 * sign,
+        step.position.1 + step.basis[axis].1 * sign,
+      ));
+
+      let mut next = Step {
+        position: expected,
+        basis: step.basis,
+        missed: step.missed + 1,
+      };
+      if let Some(found) = claim(&mut index, neighbour, expected, step.basis) {
+        // the spacing changes gradually across the image, so the steps to the nearest corners
+        // are the best guess for the next ones in the same directions
+        let taken = difference(found, step.position);
+        next.basis[axis] = (taken.0 * sign, taken.1 * sign);
+        for side in [1, -1].iter() {
+          let beside = (neighbour.0 + across.0 * side, neighbour.1 + across.1 * side);
+          if let Some(corner) = found_at(&index, beside) {
+            let taken = difference(corner, found);
+            next.basis[1 - axis] = (taken.0 * *side as f32, taken.1 * *side as f32);
+            break;
+          }
+        }
+        next.position = found;
+        next.missed = 0;
+      }
+      visited.insert(neighbour, next);
+      if next.missed <= MAX_GAP {
+        queue.push_back(neighbour);
+      }
+    }
+  }
+
+  return index;
+}
+
+#[test]
+fn should_index_lattice_through_gaps_and_outliers() {
+  // a lattice in strong perspective, so the corners shrink towards the top
+  let to_image = |i: f32, j: f32| {
+    let w = 1.0 + 0.06 * (8.0 - j);
+    (200.0 + (i - 4.0) * 40.0 / w, 40.0 + j * 40.0 / w.powf(1.5))
+  };
+  let missing = [(3, 3), (0, 8), (6, 1)];
+  let mut points = Vec::new();
+  let mut truth = Vec::new();
+  for j in 0..9 {
+    for i in 0..9 {
+      if missing.contains(&(i, j)) {
+        continue;
+      }
+      let jitter = ((i * 7 + j * 3) % 5) as f32 * 0.3 - 0.6;
+      let (x, y) = to_image(i as f32, j as f32);
+      points.push((x + jitter, y - jitter));
+      truth.push(Some((i - 4, j - 6)));
+    }
+  }
+  // strays in the middle of squares and off the board
+  for (i, j) in [(2.5, 2.5), (5.5, 6.5), (-1.5, 4.0), (4.0, 10.5)].iter() {
+    points.push(to_image(*i, *j));
+    truth.push(None);
+  }
+
+  let seed = [
+    to_image(4.0, 6.0),
+    to_image(5.0, 6.0),
+    to_image(5.0, 7.0),
+    to_image(4.0, 7.0),
+  ];
+  let index = index_lattice(&points, seed);
+  assert_eq!(index.coordinates(), &truth[..]);
+  assert_eq!(index.len(), 81 - missing.len());
+  assert_eq!(index.point_at(-4, -6), Some(0));
+  assert_eq!(index.point_at(-1, -3), None);
+
+  // every square but the ones touching a missing corner
+  let squares = index.observed_squares();
+  assert_eq!(squares.len(), 64 - 4 - 1 - 4);
+  assert_eq!(squares[0], (-4, -6));
+  assert!(!squares.contains(&(-2, -4)));
+}
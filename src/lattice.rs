@@ -1,3 +1,5 @@
+use crate::error::SegmentError;
+use crate::spatial::GridIndex;
 use chfft::RFft1D;
 use image::GrayImage;
 use std::collections::HashSet;
@@ -5,7 +7,7 @@ use std::collections::HashSet;
 #[inline]
 fn get_circle(i: &GrayImage, x: u32, y: u32, r: u32) -> Option<Vec<u8>> {
   let (width, height) = i.dimensions();
-  if x < r || x > width - (r + 1) || y < r || y > height - (r + 1) {
+  if x < r || x.saturating_add(r) >= width || y < r || y.saturating_add(r) >= height {
     return None;
   }
 
@@ -30,33 +32,130 @@ fn get_circle(i: &GrayImage, x: u32, y: u32, r: u32) -> Option<Vec<u8>> {
   return Some(circle);
 }
 
-pub fn is_corner(i: &GrayImage, x: u32, y: u32) -> bool {
+/// How much more the pixels on a circle around `(x, y)` alternate twice, as around a square
+/// corner, than once, as along an edge. `None` when the circle leaves the image.
+pub fn corner_response(i: &GrayImage, x: u32, y: u32) -> Option<f32> {
   let r = 5;
-  match get_circle(i, x, y, r) {
-    Some(values) => {
-      let input: Vec<f64> = values.iter().map(|v| *v as f64).collect();
-      let mut fft = RFft1D::<f64>::new(values.len());
-      let output = fft.forward(&input);
+  let values = get_circle(i, x, y, r)?;
+  let input: Vec<f64> = values.iter().map(|v| *v as f64).collect();
+  let mut fft = RFft1D::<f64>::new(values.len());
+  let output = fft.forward(&input);
 
-      let mag_1 = (output[1].re.powf(2.0) + output[1].im.powf(2.0)).sqrt();
-      let mag_2 = (output[2].re.powf(2.0) + output[2].im.powf(2.0)).sqrt();
+  let mag_1 = (output[1].re.powf(2.0) + output[1].im.powf(2.0)).sqrt();
+  let mag_2 = (output[2].re.powf(2.0) + output[2].im.powf(2.0)).sqrt();
+  return Some((mag_2 - mag_1) as f32);
+}
 
-      return mag_2 > mag_1;
-    }
-    None => {
-      return false;
+pub fn is_corner(i: &GrayImage, x: u32, y: u32) -> bool {
+  return corner_response(i, x, y).is_some_and(|response| response > 0.0);
+}
+
+// half the size of the window a saddle is fitted to around a corner
+const SADDLE_RADIUS: i32 = 4;
+
+// refinement stops once a step moves the corner less than this
+const SADDLE_TOLERANCE: f32 = 0.01;
+
+const SADDLE_ITERATIONS: usize = 10;
+
+// a saddle further than this from where the corner was found is most likely a neighbouring one
+const MAX_SADDLE_DRIFT: f32 = 2.0;
+
+/// Intensity between the four pixels around `(x, y)`, which must lie inside the image.
+pub fn bilinear(i: &GrayImage, x: f32, y: f32) -> f32 {
+  let (x0, y0) = (x.floor(), y.floor());
+  let (fx, fy) = (x - x0, y - y0);
+  let pixel = |px: f32, py: f32| i.get_pixel(px as u32, py as u32)[0] as f32;
+  let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+  let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+  return top * (1.0 - fy) + bottom * fy;
+}
+
+// offset from `(x, y)` to the saddle of a quadratic fitted to the intensities around it
+fn saddle_offset(i: &GrayImage, (x, y): (f32, f32)) -> Option<(f32, f32)> {
+  let sigma = SADDLE_RADIUS as f32 / 2.0;
+
+  // the window is symmetric, so the odd terms of the quadratic are fitted independently of
+  // each other and of the even ones
+  let (mut m0, mut m2, mut m4, mut m22) = (0.0, 0.0, 0.0, 0.0);
+  let (mut s0, mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+  for dy in -SADDLE_RADIUS..=SADDLE_RADIUS {
+    for dx in -SADDLE_RADIUS..=SADDLE_RADIUS {
+      let (dx, dy) = (dx as f32, dy as f32);
+      let w = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+      let v = w * bilinear(i, x + dx, y + dy);
+      m0 += w;
+      m2 += w * dx * dx;
+      m4 += w * dx.powi(4);
+      m22 += w * dx * dx * dy * dy;
+      s0 += v;
+      sx += v * dx;
+      sy += v * dy;
+      sxx += v * dx * dx;
+      syy += v * dy * dy;
+      sxy += v * dx * dy;
     }
   }
+
+  // I = a x^2 + b x y + c y^2 + d x + e y + f
+  let b = sxy / m22;
+  let d = sx / m2;
+  let e = sy / m2;
+  let p = m4 - m2 * m2 / m0;
+  let q = m22 - m2 * m2 / m0;
+  let r = sxx - m2 * s0 / m0;
+  let t = syy - m2 * s0 / m0;
+  let a = (p * r - q * t) / (p * p - q * q);
+  let c = (p * t - q * r) / (p * p - q * q);
+
+  // the gradient vanishes at the saddle, where the hessian has eigenvalues of opposite sign
+  let determinant = 4.0 * a * c - b * b;
+  if determinant >= 0.0 {
+    return None;
+  }
+  return Some((
+    (b * e - 2.0 * c * d) / determinant,
+    (b * d - 2.0 * a * e) / determinant,
+  ));
 }
 
-fn distance_squared((ax, ay): (f32, f32), (bx, by): (f32, f32)) -> f32 {
-  let a = bx - ax;
-  let b = by - ay;
-  return a.powf(2.0) + b.powf(2.0);
+/// Moves a corner found to the nearest pixel onto the saddle point of the intensity between
+/// the four squares that meet there. `None` when the neighbourhood is not saddle shaped or
+/// the saddle lies further than a few pixels away.
+pub fn refine_corner(i: &GrayImage, point: (f32, f32)) -> Option<(f32, f32)> {
+  let (width, height) = i.dimensions();
+  let margin = SADDLE_RADIUS as f32;
+  let inside = |(x, y): (f32, f32)| {
+    x >= margin
+      && y >= margin
+      && x < width as f32 - margin - 1.0
+      && y < height as f32 - margin - 1.0
+  };
+
+  let mut refined = point;
+  for _ in 0..SADDLE_ITERATIONS {
+    if !inside(refined) {
+      return None;
+    }
+    let (dx, dy) = saddle_offset(i, refined)?;
+    // a single step only trusts the fit within the window
+    let step = (dx * dx + dy * dy).sqrt();
+    let scale = if step > 1.0 { 1.0 / step } else { 1.0 };
+    refined = (refined.0 + dx * scale, refined.1 + dy * scale);
+    if step < SADDLE_TOLERANCE {
+      break;
+    }
+  }
+
+  let drift = ((refined.0 - point.0).powf(2.0) + (refined.1 - point.1).powf(2.0)).sqrt();
+  if drift > MAX_SADDLE_DRIFT || !inside(refined) {
+    return None;
+  }
+  return Some(refined);
 }
 
-fn unique_within_dist(points: &Vec<(f32, f32)>, r: f32) -> Vec<(f32, f32)> {
-  let r_squared = r.powf(2.0);
+fn unique_within_dist(points: &[(f32, f32)], r: f32) -> Vec<(f32, f32)> {
+  let index = GridIndex::new(points, r);
   let mut seen_indices = HashSet::new();
   let mut output = Vec::new();
 
@@ -66,19 +165,15 @@ fn unique_within_dist(points: &Vec<(f32, f32)>, r: f32) -> Vec<(f32, f32)> {
     }
 
     let mut neighbors: Vec<(f32, f32)> = Vec::new();
-    for neighbor_index in 0..points.len() {
+    for neighbor_index in index.within(points[current_index], r) {
       if current_index == neighbor_index {
         continue;
       }
-
-      let ds = distance_squared(points[current_index], points[neighbor_index]);
-      if ds <= r_squared {
-        neighbors.push(points[neighbor_index]);
-        seen_indices.insert(neighbor_index);
-      }
+      neighbors.push(points[neighbor_index]);
+      seen_indices.insert(neighbor_index);
     }
 
-    if neighbors.len() == 0 {
+    if neighbors.is_empty() {
       output.push(points[current_index]);
       seen_indices.insert(current_index);
     } else {
@@ -96,13 +191,56 @@ fn unique_within_dist(points: &Vec<(f32, f32)>, r: f32) -> Vec<(f32, f32)> {
   return output;
 }
 
-pub fn get_points(i: &GrayImage, intersection_points: &Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+pub fn get_points(
+  i: &GrayImage,
+  intersection_points: &[(f32, f32)],
+) -> Result<Vec<(f32, f32)>, SegmentError> {
+  if intersection_points.is_empty() {
+    return Err(SegmentError::NoIntersections);
+  }
+
+  // the intersections found around one corner all settle on its saddle once refined, so they
+  // are merged without averaging in their errors
   let mut all_corner_points: Vec<(f32, f32)> = Vec::new();
   for point in intersection_points.iter() {
-    if is_corner(&i, point.0 as u32, point.1 as u32) {
-      all_corner_points.push(*point);
+    if is_corner(i, point.0 as u32, point.1 as u32) {
+      all_corner_points.push(refine_corner(i, *point).unwrap_or(*point));
     }
   }
 
-  return unique_within_dist(&all_corner_points, 5.0);
+  let points = unique_within_dist(&all_corner_points, 5.0);
+  if points.len() < 4 {
+    return Err(SegmentError::TooFewCorners(points.len()));
+  }
+  return Ok(points);
+}
+
+#[test]
+fn should_refine_corners_to_sub_pixel() {
+  use crate::checkerboard::Checkerboard;
+
+  // squares 30 pixels wide, rotated a little and shifted off the pixel grid, on a board that
+  // covers the whole image
+  let (angle, origin) = (0.3f32, (101.37, 98.62));
+  let (sin, cos) = angle.sin_cos();
+  let image = Checkerboard {
+    samples: 4,
+    ..Checkerboard::rotated(16, 30.0, angle, origin)
+  }
+  .gray(200, 200);
+
+  for (column, row) in [(0.0, 0.0), (1.0, 0.0), (-1.0, 1.0), (1.0, -1.0)].iter() {
+    let truth = (
+      origin.0 + 30.0 * (column * cos - row * sin),
+      origin.1 + 30.0 * (column * sin + row * cos),
+    );
+    let rounded = (truth.0.round() + 1.0, truth.1.round() - 1.0);
+    let refined = refine_corner(&image, rounded).unwrap();
+    let error = ((refined.0 - truth.0).powf(2.0) + (refined.1 - truth.1).powf(2.0)).sqrt();
+    assert!(error < 0.1, "{:?} refined to {:?}", truth, refined);
+  }
+
+  // an edge has no saddle
+  let edge = GrayImage::from_fn(40, 40, |x, _| image::Luma([if x < 20 { 40 } else { 210 }]));
+  assert_eq!(refine_corner(&edge, (20.0, 20.0)), None);
 }
@@ -1,14 +1,15 @@
 use crate::bounding_box::{bounding_box, bounding_box_area, bounding_box_offset, dist_squared};
-use crate::color::turbo;
 use crate::debug::{debug_images, write_rgb};
+use crate::error::SegmentError;
+use crate::homography::fit_projection;
 use crate::lattice::get_points;
-use crate::line::get_lines;
+use crate::line::{Hough, LineDetector};
 use image::GrayImage;
 use imageproc::geometric_transformations::Projection;
 
-pub fn layer(image: &GrayImage) -> (Option<Projection>, f32) {
+pub fn layer(image: &GrayImage) -> Result<(Projection, f32), SegmentError> {
   let (width, height) = image.dimensions();
-  let lines = get_lines(&image, 100, 20);
+  let lines = Hough::default().detect(image)?;
   let mut intersection_points: Vec<(f32, f32)> = Vec::new();
   for a in lines.iter() {
     for b in lines.iter() {
@@ -18,19 +19,17 @@ pub fn layer(image: &GrayImage) -> (Option<Projection>, f32) {
     }
   }
 
-  let points = get_points(&image, &intersection_points);
-  let mbb = bounding_box(&points);
+  let points = get_points(image, &intersection_points)?;
+  let mbb = bounding_box(&points)?;
   let mbb_area = bounding_box_area(mbb);
-  dbg!(mbb_area);
   let input_area = (width * height) as f32;
-  dbg!(input_area);
+  if mbb_area < input_area / 20.0 {
+    return Err(SegmentError::BoardTooSmall);
+  }
   let error = 1.0 - mbb_area / input_area;
-  dbg!(error);
   let offset = (error * mbb_area).sqrt().max(mbb_area.sqrt() / 6.0);
-  dbg!(offset);
   // check error if it should offset
   let offset_mbb = bounding_box_offset(mbb, offset / 4.0);
-  println!("{:?}", offset_mbb);
 
   let closest_offset_mbb: Vec<(f32, f32)> = offset_mbb
     .to_vec()
@@ -38,8 +37,8 @@ pub fn layer(image: &GrayImage) -> (Option<Projection>, f32) {
     .map(|op| {
       let mut closest_index = 0;
       let mut min_d = dist_squared(*op, intersection_points[0]).abs();
-      for index in 1..intersection_points.len() {
-        let d = dist_squared(*op, intersection_points[index]).abs();
+      for (index, point) in intersection_points.iter().enumerate().skip(1) {
+        let d = dist_squared(*op, *point).abs();
         if d < min_d {
           min_d = d;
           closest_index = index;
@@ -108,11 +107,10 @@ pub fn layer(image: &GrayImage) -> (Option<Projection>, f32) {
       );
     }
 
-    write_rgb(&intersection_image, "lattice-intersections");
+    write_rgb(&intersection_image, "lattice-intersections")?;
 
     let mut mbb_image = image::DynamicImage::ImageLuma8(image.clone()).to_rgb8();
-    for i in 0..mbb.len() {
-      let p = mbb[i];
+    for p in mbb.iter() {
       imageproc::drawing::draw_filled_circle_mut(
         &mut mbb_image,
         (p.0 as i32, p.1 as i32),
@@ -120,8 +118,7 @@ pub fn layer(image: &GrayImage) -> (Option<Projection>, f32) {
         image::Rgb::<u8>([255, 0, 0]),
       );
     }
-    for i in 0..offset_mbb.len() {
-      let p = offset_mbb[i];
+    for p in offset_mbb.iter() {
       imageproc::drawing::draw_filled_circle_mut(
         &mut mbb_image,
         (p.0 as i32, p.1 as i32),
@@ -129,11 +126,10 @@ pub fn layer(image: &GrayImage) -> (Option<Projection>, f32) {
         image::Rgb::<u8>([0, 255, 0]),
       );
     }
-    write_rgb(&mbb_image, "mbb-offset");
+    write_rgb(&mbb_image, "mbb-offset")?;
   }
 
-  return (
-    Projection::from_control_points(project_from, project_to),
-    error,
-  );
+  let projection =
+    fit_projection(&project_from, &project_to).ok_or(SegmentError::DegenerateHomography)?;
+  return Ok((projection, error));
 }
@@ -1,30 +1,81 @@
-use image::imageops::{blur, contrast, resize, unsharpen, FilterType};
-use image::{GrayImage, ImageBuffer};
+#![allow(clippy::needless_return)]
+
+use image::imageops::{blur, resize, unsharpen, FilterType};
+use image::{GenericImageView, GrayImage, ImageBuffer};
 use imageproc::geometric_transformations::{warp_with, Interpolation, Projection};
 use wasm_bindgen::prelude::*;
 
 mod bounding_box;
+#[cfg(test)]
+mod checkerboard;
 mod cluster;
 mod color;
+mod config;
+mod corners;
 mod debug;
+#[allow(dead_code)]
 mod delaunay_triangulation;
+mod error;
+mod homography;
+mod indexing;
 mod lattice;
 mod layer;
 mod line;
+mod lsd;
+mod moves;
+mod occlusion;
+mod occupancy;
+mod orientation;
+mod pieces;
+#[allow(dead_code)]
 mod point;
+mod position;
+mod recorder;
 mod sample_consensus;
+mod segmentation;
+mod smoothing;
+mod spatial;
+mod square;
+mod tracker;
+mod vanishing;
 
-use cluster::{dbscan, mean_shift};
-use lattice::get_points;
+use cluster::dbscan;
+use lattice::{get_points, refine_corner};
 use layer::layer;
-use line::get_lines;
+use vanishing::group_lines;
+
+pub use cluster::{Clusterer, Clustering, Dbscan, KMeans, MeanShift};
+pub use config::{BoardFit, CornerSource, LineSource, SegmentConfig};
+pub use corners::{detect_corners, lattice_corners, Corner, CornerParams};
+pub use error::SegmentError;
+pub use homography::{fit_projection, reprojection_errors, HomographyFit, Ransac};
+pub use indexing::{index_lattice, LatticeIndex};
+pub use line::{AdaptiveHough, Hough, Line, LineDetector};
+pub use lsd::Lsd;
+pub use moves::{infer_move, infer_move_from_occupancy, AmbiguousDiff, Move, MoveKind};
+pub use occlusion::{FrameState, OcclusionDetector};
+pub use occupancy::{occupancy, square_features_all, Occupancy, SquareFeatures};
+pub use orientation::Rotation;
+pub use pieces::{
+    piece_colors, starting_position, Piece, PieceColor, PieceColors, PieceKind, PieceMatch,
+    PieceTemplates, TEMPLATE_SIZE,
+};
+pub use point::Point;
+pub use position::{CastlingRights, FenError, Position, STARTING_FEN};
+pub use recorder::{GameRecorder, RecordedMove, RecorderError};
+pub use sample_consensus::{fit_grid, GridFit};
+pub use segmentation::{BoardSegmentation, LATTICE_SIZE};
+pub use smoothing::CornerFilter;
+pub use square::{ParseSquareError, Square};
+pub use tracker::BoardTracker;
+pub use vanishing::VanishingPoint;
 
 // http://wiki.bitplan.com/index.php/PlayChessWithAWebCam/Papers#Stonewall_Chess_Computer_Vision
 // https://www.esimov.com/2020/01/pigo-wasm#.X_0caWRKjUL
 // https://github.com/esimov/pigo
 // https://github.com/ColinEberhardt/wasm-sudoku-solver
 
-pub fn segment_layered(image: &GrayImage) {
+pub fn segment_layered(image: &GrayImage) -> Result<GrayImage, SegmentError> {
     let resized = resize(image, 400, 400, FilterType::Gaussian);
     let mut next_image = resized;
     let mut index = 0;
@@ -33,29 +84,27 @@ pub fn segment_layered(image: &GrayImage) {
             break;
         }
 
-        let (p, e) = layer(&next_image);
-        match p {
-            Some(projection) => {
-                next_image = warp_with(
-                    &next_image,
-                    |x, y| projection.invert() * (x, y),
-                    Interpolation::Bilinear,
-                    image::Luma([0]),
-                );
-            }
-            None => {
-                break;
-            }
-        }
+        // later layers only refine the first, keep what has been found so far if they fail
+        let (projection, e) = match layer(&next_image) {
+            Ok(result) => result,
+            Err(err) if index == 0 => return Err(err),
+            Err(_) => break,
+        };
+        next_image = warp_with(
+            &next_image,
+            |x, y| projection.invert() * (x, y),
+            Interpolation::Bilinear,
+            image::Luma([0]),
+        );
 
-        dbg!(e);
         if e < 0.5 {
             break;
         }
-        debug::write_gray(&next_image, format!("layer_{}", index).as_str());
+        debug::write_gray(&next_image, format!("layer_{}", index).as_str())?;
         index += 1;
     }
-    debug::write_gray(&next_image, "layer_result");
+    debug::write_gray(&next_image, "layer_result")?;
+    return Ok(next_image);
 }
 
 #[wasm_bindgen]
@@ -83,30 +132,23 @@ fn from_js_image_buffer(width: u32, height: u32, buf: Vec<u8>) -> image::RgbImag
 
 #[wasm_bindgen]
 pub fn wasm_bounding_box(width: u32, height: u32, buf: Vec<u8>) -> Vec<f32> {
+    return bounding_box_corners(width, height, buf).unwrap_or_default();
+}
+
+fn bounding_box_corners(width: u32, height: u32, buf: Vec<u8>) -> Result<Vec<f32>, SegmentError> {
     let w = 400;
     let h = 400;
     let img = from_js_image_buffer(width, height, buf);
     let scaled = resize(&img, w, h, FilterType::Triangle);
     let gray = &image::DynamicImage::ImageRgb8(scaled.clone()).into_luma8();
-    let lines = get_lines(&gray, 100, 20);
-    let mut intersection_points: Vec<(f32, f32)> = Vec::new();
-    for a in lines.iter() {
-        for b in lines.iter() {
-            if let Some(point) = a.intersection(b) {
-                intersection_points.push(point);
-            }
-        }
-    }
-    let points = get_points(&gray, &intersection_points);
-    if points.len() < 4 {
-        return vec![];
-    }
-
-    let mbb = bounding_box::bounding_box(&points);
+    let lines = Hough::default().detect(gray)?;
+    let intersection_points = corner_candidates(&lines);
+    let points = get_points(gray, &intersection_points)?;
+    let mbb = bounding_box::bounding_box(&points)?;
     let mbb_area = bounding_box::bounding_box_area(mbb);
     let input_area = (w * h) as f32;
     if mbb_area < input_area / 20.0 {
-        return Vec::new();
+        return Err(SegmentError::BoardTooSmall);
     }
     let error = 1.0 - mbb_area / input_area;
     let offset = (error * mbb_area).sqrt().max(mbb_area.sqrt() / 6.0);
@@ -119,8 +161,8 @@ pub fn wasm_bounding_box(width: u32, height: u32, buf: Vec<u8>) -> Vec<f32> {
         .map(|op| {
             let mut closest_index = 0;
             let mut min_d = bounding_box::dist_squared(*op, intersection_points[0]).abs();
-            for index in 1..intersection_points.len() {
-                let d = bounding_box::dist_squared(*op, intersection_points[index]).abs();
+            for (index, point) in intersection_points.iter().enumerate().skip(1) {
+                let d = bounding_box::dist_squared(*op, *point).abs();
                 if d < min_d {
                     min_d = d;
                     closest_index = index;
@@ -135,20 +177,72 @@ pub fn wasm_bounding_box(width: u32, height: u32, buf: Vec<u8>) -> Vec<f32> {
         output.push(*x);
         output.push(*y);
     }
-    return output;
+    return Ok(output);
 }
 
-pub fn segment(i: &image::DynamicImage) {
-    let input_image_rgb = i.to_rgb8();
-    let formatted_rgb = resize(&input_image_rgb, 400, 400, FilterType::Gaussian);
+// every frame is analysed at this size regardless of its resolution
+const WORKING_SIZE: u32 = 400;
+
+// the frame resized to the working size, in colour and as sharpened grayscale
+fn working_images(i: &image::DynamicImage) -> (image::RgbImage, GrayImage) {
+    let formatted_rgb = resize(&i.to_rgb8(), WORKING_SIZE, WORKING_SIZE, FilterType::Gaussian);
     let formatted_gray = unsharpen(
         &image::DynamicImage::ImageRgb8(formatted_rgb.clone()).into_luma8(),
         2.0,
         50,
     );
+    return (formatted_rgb, formatted_gray);
+}
 
-    let lines = get_lines(&formatted_gray, 100, 20);
+// rectifies the board with `projection`, which maps working coordinates onto the board, and
+// maps the result back to the coordinates of the `input_size` frame. The rotation of the board
+// is detected unless it is already known.
+fn board_segmentation(
+    formatted_rgb: &image::RgbImage,
+    projection: Projection,
+    input_size: (u32, u32),
+    rotation: Option<Rotation>,
+) -> Result<BoardSegmentation, SegmentError> {
+    let (w, h) = formatted_rgb.dimensions();
+    let inverse_projection = projection.invert();
+    let warped_rgb = warp_with(
+        formatted_rgb,
+        |x, y| inverse_projection * (x, y),
+        Interpolation::Bilinear,
+        image::Rgb([0, 0, 0]),
+    );
+    crate::debug::write_rgb(&warped_rgb, "warped")?;
+
+    // the lattice was found on the resized image, map it back to the caller's coordinates
+    let scale_x = input_size.0 as f32 / w as f32;
+    let scale_y = input_size.1 as f32 / h as f32;
+    let board_corners = [
+        (0.0, 0.0),
+        (w as f32, 0.0),
+        (w as f32, h as f32),
+        (0.0, h as f32),
+    ];
+    let mut corners = [(0.0, 0.0); 4];
+    for index in 0..corners.len() {
+        let (x, y) = inverse_projection * board_corners[index];
+        corners[index] = (x * scale_x, y * scale_y);
+    }
+    let source_projection = projection * Projection::scale(1.0 / scale_x, 1.0 / scale_y);
+
+    return Ok(match rotation {
+        Some(rotation) => {
+            BoardSegmentation::with_rotation(corners, source_projection, warped_rgb, rotation)
+        }
+        None => BoardSegmentation::new(corners, source_projection, warped_rgb),
+    });
+}
 
+pub fn segment(i: &image::DynamicImage) -> Result<BoardSegmentation, SegmentError> {
+    return segment_with_config(i, &SegmentConfig::default());
+}
+
+// every point where two of `lines` cross
+fn intersections(lines: &[line::Line]) -> Vec<(f32, f32)> {
     let mut intersection_points: Vec<(f32, f32)> = Vec::new();
     for a in lines.iter() {
         for b in lines.iter() {
@@ -158,7 +252,65 @@ pub fn segment(i: &image::DynamicImage) {
         }
     }
 
-    let points = get_points(&formatted_gray, &intersection_points);
+    return intersection_points;
+}
+
+// points where lines running in different directions cross
+fn corner_candidates(lines: &[line::Line]) -> Vec<(f32, f32)> {
+    // lines of the same direction only meet far from any corner, unless too few lines were
+    // found to tell the directions apart
+    return match group_lines(lines) {
+        Some(families) => families.intersections(lines),
+        None => intersections(lines),
+    };
+}
+
+// corners of squares found by scanning the whole image
+fn dense_corners(gray: &GrayImage, params: &CornerParams) -> Result<Vec<(f32, f32)>, SegmentError> {
+    let corners = lattice_corners(&detect_corners(gray, params));
+    if corners.len() < 4 {
+        return Err(SegmentError::TooFewCorners(corners.len()));
+    }
+    return Ok(corners.iter().map(|corner| corner.pos).collect());
+}
+
+/// Vanishing points of the two directions of the lines `detector` finds in `i`, in its
+/// coordinates. These are the directions of the board edges when the board fills most of the
+/// image. Pass `&config.lines` to use the detector of a `SegmentConfig`.
+pub fn vanishing_points(
+    i: &image::DynamicImage,
+    detector: &dyn LineDetector,
+) -> Result<[VanishingPoint; 2], SegmentError> {
+    let (_, formatted_gray) = working_images(i);
+    let lines = detector.detect(&formatted_gray)?;
+    let families = group_lines(&lines).ok_or(SegmentError::NoLineFamilies)?;
+    let (input_width, input_height) = i.dimensions();
+    let (w, h) = formatted_gray.dimensions();
+    let (sx, sy) = (input_width as f32 / w as f32, input_height as f32 / h as f32);
+    let [first, second] = families.vanishing_points;
+    return Ok([first.scale(sx, sy), second.scale(sx, sy)]);
+}
+
+/// Like `segment`, with the stages of the pipeline chosen by `config`.
+pub fn segment_with_config(
+    i: &image::DynamicImage,
+    config: &SegmentConfig,
+) -> Result<BoardSegmentation, SegmentError> {
+    let (formatted_rgb, formatted_gray) = working_images(i);
+
+    let (lines, intersection_points, points) = match &config.corners {
+        CornerSource::Lines => {
+            let lines = config.lines.detect(&formatted_gray)?;
+            let intersection_points = corner_candidates(&lines);
+            let points = get_points(&formatted_gray, &intersection_points)?;
+            (lines, intersection_points, points)
+        }
+        CornerSource::Dense(params) => {
+            // every detected corner is both a candidate and a point of the lattice
+            let points = dense_corners(&formatted_gray, params)?;
+            (Vec::new(), points.clone(), points)
+        }
+    };
 
     let (w, h) = formatted_gray.dimensions();
     if crate::debug::debug_images() {
@@ -207,9 +359,14 @@ pub fn segment(i: &image::DynamicImage) {
             );
         }
 
-        debug::write_rgb(&intersection_image, "lattice-intersections");
+        debug::write_rgb(&intersection_image, "lattice-intersections")?;
 
-        let clustered = mean_shift(&points);
+        let clustered: Vec<usize> = MeanShift::default()
+            .cluster(&points)
+            .labels
+            .iter()
+            .map(|label| label.unwrap_or(0))
+            .collect();
         let mut clusters_image: image::RgbImage = image::ImageBuffer::new(w, h);
         let mut clusters_max = 0;
         for c in clustered.iter() {
@@ -226,7 +383,7 @@ pub fn segment(i: &image::DynamicImage) {
                 image::Rgb::<u8>(color::turbo(clustered[i] as f32 / clusters_max as f32)),
             );
         }
-        debug::write_rgb(&clusters_image, "clusters");
+        debug::write_rgb(&clusters_image, "clusters")?;
 
         let db_clustered = dbscan(&points, 75.0, 5);
         let mut db_clusters_image: image::RgbImage = image::ImageBuffer::new(w, h);
@@ -247,7 +404,7 @@ pub fn segment(i: &image::DynamicImage) {
                 )),
             );
         }
-        debug::write_rgb(&db_clusters_image, "db_clusters");
+        debug::write_rgb(&db_clusters_image, "db_clusters")?;
 
         let mut sum_x = 0.0;
         let mut sum_y = 0.0;
@@ -257,7 +414,6 @@ pub fn segment(i: &image::DynamicImage) {
         }
         let avg_x = sum_x / points.len() as f32;
         let avg_y = sum_y / points.len() as f32;
-        println!("{}, {}", avg_x, avg_y);
 
         let mut center_image: image::RgbImage = image::ImageBuffer::new(w, h);
         imageproc::drawing::draw_filled_circle_mut(
@@ -295,23 +451,44 @@ pub fn segment(i: &image::DynamicImage) {
                 image::Rgb::<u8>(color::turbo(v)),
             );
         }
-        debug::write_rgb(&center_image, "center-of-points");
+        debug::write_rgb(&center_image, "center-of-points")?;
     }
 
-    println!("points {:?}", points);
+    let projection = match config.board {
+        BoardFit::BoundingBox => {
+            // the outer edge of a board is not a corner where four squares meet, so dense
+            // detection finds the lattice one square in from it
+            let inset = match config.corners {
+                CornerSource::Lines => 0.0,
+                CornerSource::Dense(_) => 1.0 / (LATTICE_SIZE - 1) as f32,
+            };
+            bounding_box_projection(&formatted_gray, &points, &intersection_points, inset)?
+        }
+        BoardFit::Grid => grid_projection(&formatted_gray, &points)?,
+    };
 
-    let mbb = bounding_box::bounding_box(&points);
-    println!("{:?}", mbb);
+    return board_segmentation(&formatted_rgb, projection, i.dimensions(), None);
+}
+
+// the board is the rectangle around the corners, grown a little and moved onto the nearest
+// candidates, and `inset` of its size inside the outer edge of the board
+fn bounding_box_projection(
+    formatted_gray: &GrayImage,
+    points: &[(f32, f32)],
+    intersection_points: &[(f32, f32)],
+    inset: f32,
+) -> Result<Projection, SegmentError> {
+    let (w, h) = formatted_gray.dimensions();
+    let mbb = bounding_box::bounding_box(points)?;
     let mbb_area = bounding_box::bounding_box_area(mbb);
     let input_area = (w * h) as f32;
-    println!("input_area {}, mbb_area {:?}", input_area, mbb_area);
+    if mbb_area < input_area / 20.0 {
+        return Err(SegmentError::BoardTooSmall);
+    }
     let error = 1.0 - mbb_area / input_area;
     let offset = (error * mbb_area).sqrt().max(mbb_area.sqrt() / 6.0);
-    println!("error {}, offset {:?}", error, offset);
     // check error if it should offset
     let offset_mbb = bounding_box::bounding_box_offset(mbb, offset / 4.0);
-    println!("offset mbb {:?}", offset_mbb);
-    // println!("error {}, offset {}", error, offset);
 
     let closest_offset_mbb: Vec<(f32, f32)> = offset_mbb
         .to_vec()
@@ -319,21 +496,21 @@ pub fn segment(i: &image::DynamicImage) {
         .map(|op| {
             let mut closest_index = 0;
             let mut min_d = bounding_box::dist_squared(*op, intersection_points[0]).abs();
-            for index in 1..intersection_points.len() {
-                let d = bounding_box::dist_squared(*op, intersection_points[index]).abs();
+            for (index, point) in intersection_points.iter().enumerate().skip(1) {
+                let d = bounding_box::dist_squared(*op, *point).abs();
                 if d < min_d {
                     min_d = d;
                     closest_index = index;
                 }
             }
-            return intersection_points[closest_index];
+            let closest = intersection_points[closest_index];
+            return refine_corner(formatted_gray, closest).unwrap_or(closest);
         })
         .collect();
 
     if crate::debug::debug_images() {
         let mut mbb_image = image::DynamicImage::ImageLuma8(formatted_gray.clone()).to_rgb8();
-        for i in 0..mbb.len() {
-            let p = mbb[i];
+        for p in mbb.iter() {
             imageproc::drawing::draw_filled_circle_mut(
                 &mut mbb_image,
                 (p.0 as i32, p.1 as i32),
@@ -341,8 +518,7 @@ pub fn segment(i: &image::DynamicImage) {
                 image::Rgb::<u8>([255, 0, 0]),
             );
         }
-        for i in 0..offset_mbb.len() {
-            let p = offset_mbb[i];
+        for p in offset_mbb.iter() {
             imageproc::drawing::draw_filled_circle_mut(
                 &mut mbb_image,
                 (p.0 as i32, p.1 as i32),
@@ -350,8 +526,7 @@ pub fn segment(i: &image::DynamicImage) {
                 image::Rgb::<u8>([0, 255, 0]),
             );
         }
-        for i in 0..closest_offset_mbb.len() {
-            let p = closest_offset_mbb[i];
+        for p in closest_offset_mbb.iter() {
             imageproc::drawing::draw_filled_circle_mut(
                 &mut mbb_image,
                 (p.0 as i32, p.1 as i32),
@@ -359,31 +534,110 @@ pub fn segment(i: &image::DynamicImage) {
                 image::Rgb::<u8>([0, 0, 255]),
             );
         }
-        crate::debug::write_rgb(&mbb_image, "mbb-offset");
+        crate::debug::write_rgb(&mbb_image, "mbb-offset")?;
     }
 
-    let projection = Projection::from_control_points(
-        [
-            closest_offset_mbb[0],
-            closest_offset_mbb[1],
-            closest_offset_mbb[2],
-            closest_offset_mbb[3],
-        ],
-        [
-            (0.0, 0.0),
-            (w as f32, 0.0),
-            (w as f32, h as f32),
-            (0.0, h as f32),
-        ],
+    // a counter-clockwise quad would mirror the board and scramble its orientation
+    let board_corners = bounding_box::quad_clockwise([
+        closest_offset_mbb[0],
+        closest_offset_mbb[1],
+        closest_offset_mbb[2],
+        closest_offset_mbb[3],
+    ]);
+    let (left, top) = (w as f32 * inset, h as f32 * inset);
+    let (right, bottom) = (w as f32 - left, h as f32 - top);
+    let projection = fit_projection(
+        &board_corners,
+        &[(left, top), (right, top), (right, bottom), (left, bottom)],
     )
-    .expect("Could not compute projection matrix")
-    .invert();
+    .ok_or(SegmentError::DegenerateHomography)?;
 
-    let warped_rgb = warp_with(
-        &formatted_rgb,
-        |x, y| projection * (x, y),
-        Interpolation::Bilinear,
-        image::Rgb([0, 0, 0]),
-    );
-    crate::debug::write_rgb(&warped_rgb, "warped");
+    return Ok(projection);
+}
+
+// the board is the eight by eight squares of the grid fitted to the corners that cover them
+// corners further than this fraction of a square from the fitted board are left out of it
+const GRID_INLIER_FRACTION: f32 = 0.1;
+
+fn grid_projection(
+    formatted_gray: &GrayImage,
+    points: &[(f32, f32)],
+) -> Result<Projection, SegmentError> {
+    let points: Vec<Point> = points.iter().map(|point| Point::from(*point)).collect();
+    let fit = fit_grid(formatted_gray, &points).ok_or(SegmentError::NoGridFound)?;
+
+    // the board is the run of eight squares holding the most inliers, the middle one of those
+    // when the outer edge was not found
+    let squares = (LATTICE_SIZE - 1) as i32;
+    let origin = |axis: fn(&(i32, i32)) -> i32| {
+        let values: Vec<i32> = fit.coordinates.iter().map(axis).collect();
+        let min = values.iter().copied().min().unwrap_or(0);
+        let max = values.iter().copied().max().unwrap_or(0);
+        let centre = (min + max) as f32 / 2.0;
+        let mut best = (0, 0.0, min);
+        for start in (max - squares).min(min)..=min.max(max - squares) {
+            let inside = values
+                .iter()
+                .filter(|value| **value >= start && **value <= start + squares)
+                .count();
+            let offset = ((start as f32 + squares as f32 / 2.0) - centre).abs();
+            if inside > best.0 || (inside == best.0 && offset < best.1) {
+                best = (inside, offset, start);
+            }
+        }
+        return best.2;
+    };
+    let column = origin(|coordinate| coordinate.0);
+    let row = origin(|coordinate| coordinate.1);
+
+    // every lattice corner on the board pins the projection, not just the four outer ones
+    let (w, h) = formatted_gray.dimensions();
+    let square = (w as f32 / squares as f32, h as f32 / squares as f32);
+    let mut from = Vec::new();
+    let mut to = Vec::new();
+    for (point, (x, y)) in fit.inliers.iter().zip(fit.coordinates.iter()) {
+        let (x, y) = (x - column, y - row);
+        if x >= 0 && y >= 0 && x <= squares && y <= squares {
+            from.push((point.x as f32, point.y as f32));
+            to.push((x as f32 * square.0, y as f32 * square.1));
+        }
+    }
+    let board_fit = Ransac {
+        threshold: square.0.min(square.1) * GRID_INLIER_FRACTION,
+        ..Ransac::default()
+    }
+    .fit(&from, &to)
+    .ok_or(SegmentError::NoGridFound)?;
+    return Ok(board_fit.projection);
+}
+
+#[test]
+fn should_not_find_board_in_blank_image() {
+    let blank = image::DynamicImage::ImageRgb8(image::RgbImage::new(200, 200));
+    assert!(matches!(segment(&blank), Err(SegmentError::NoLinesFound)));
+}
+
+#[test]
+fn should_segment_board_from_dense_corners() {
+    let corners = [(70.0, 60.0), (330.0, 80.0), (350.0, 340.0), (50.0, 320.0)];
+    let frame = checkerboard::Checkerboard {
+        dark: image::Rgb([60, 60, 60]),
+        light: image::Rgb([200, 200, 200]),
+        ..checkerboard::Checkerboard::through(corners)
+    }
+    .rgb(400, 400);
+
+    let frame = image::DynamicImage::ImageRgb8(frame);
+    for board in [BoardFit::BoundingBox, BoardFit::Grid].iter() {
+        let config = SegmentConfig {
+            board: *board,
+            ..SegmentConfig::dense()
+        };
+        let segmentation = segment_with_config(&frame, &config).unwrap();
+        for corner in corners.iter() {
+            assert!(segmentation.corners.iter().any(|found| {
+                ((found.0 - corner.0).powf(2.0) + (found.1 - corner.1).powf(2.0)).sqrt() < 3.0
+            }));
+        }
+    }
 }
@@ -1,9 +1,13 @@
 use image::{DynamicImage, GrayImage, Rgb};
 use imageproc::drawing::draw_line_segment_mut;
 use imageproc::edges::canny;
+use imageproc::gradients::sobel_gradients;
 use imageproc::hough::{detect_lines, LineDetectionOptions, PolarLine};
 
 use crate::debug;
+use crate::error::SegmentError;
+use crate::segmentation::LATTICE_SIZE;
+use crate::vanishing::group_lines;
 
 pub fn intersection(
   a_start: (f32, f32),
@@ -34,15 +38,36 @@ pub fn intersection(
   return Some((x, y));
 }
 
-#[derive(Debug)]
+/// A straight line through the image, given by two points on it. Lines from the Hough
+/// transform run from one image border to another, line segments end where the edge they
+/// follow does.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Line {
   pub start: (f32, f32),
   pub end: (f32, f32),
+
+  /// How much evidence the image holds for the line, only comparable between lines found by
+  /// the same detector. Zero when unknown.
+  pub strength: f32,
 }
 
 impl Line {
   pub fn new(start: (f32, f32), end: (f32, f32)) -> Self {
-    Line { start, end }
+    Line {
+      start,
+      end,
+      strength: 0.0,
+    }
+  }
+
+  pub fn length(&self) -> f32 {
+    return ((self.end.0 - self.start.0).powf(2.0) + (self.end.1 - self.start.1).powf(2.0)).sqrt();
+  }
+
+  /// Direction in radians, in `[0, pi)`.
+  pub fn angle(&self) -> f32 {
+    let angle = (self.end.1 - self.start.1).atan2(self.end.0 - self.start.0);
+    return angle.rem_euclid(std::f32::consts::PI);
   }
 
   pub fn intersection(&self, other: &Line) -> Option<(f32, f32)> {
@@ -128,40 +153,256 @@ fn polar_line_points(line: PolarLine, image_width: u32, image_height: u32) -> Op
   None
 }
 
-pub fn get_lines(i: &GrayImage, vote_threshold: u32, suppression_radius: u32) -> Vec<Line> {
-  let edges = canny(&i, 50.0, 80.0);
-  debug::write_gray(&edges, "line-canny");
-  let (image_width, image_height) = i.dimensions();
-
-  let lines = detect_lines(
-    &edges,
-    LineDetectionOptions {
-      vote_threshold,
-      suppression_radius,
-    },
-  );
-
-  if crate::debug::debug_images() {
-    let mut lines_image = DynamicImage::ImageLuma8(i.clone()).to_rgb8();
-    let len = lines.len();
-    for index in 0..lines.len() {
-      let v = (index as f32 / len as f32 * 255.0) as u8;
-      let mut color = Rgb::<u8>([100, v, 200]);
-      if index == 0 {
-        color = Rgb::<u8>([255, 0, 0]);
-      } else if index == len - 1 {
-        color = Rgb::<u8>([0, 255, 0]);
+/// A way of finding the straight edges in an image.
+pub trait LineDetector {
+  /// Every line found in `i`, or `SegmentError::NoLinesFound`.
+  fn detect(&self, i: &GrayImage) -> Result<Vec<Line>, SegmentError>;
+}
+
+/// The Hough transform over the edges found by the Canny detector. Lines are found at whole
+/// degree angles and run across the whole image, their strength is the number of edge pixels
+/// along them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hough {
+  /// Gradient below which the Canny detector drops edges not connected to stronger ones.
+  pub canny_low: f32,
+
+  /// Gradient above which the Canny detector always keeps an edge.
+  pub canny_high: f32,
+
+  /// Edge pixels a line must pass through.
+  pub vote_threshold: u32,
+
+  /// Lines within this many pixels and degrees of a line with more votes are dropped.
+  pub suppression_radius: u32,
+}
+
+impl Default for Hough {
+  fn default() -> Self {
+    Hough {
+      canny_low: 50.0,
+      canny_high: 80.0,
+      vote_threshold: 100,
+      suppression_radius: 20,
+    }
+  }
+}
+
+impl LineDetector for Hough {
+  fn detect(&self, i: &GrayImage) -> Result<Vec<Line>, SegmentError> {
+    let edges = canny(i, self.canny_low, self.canny_high);
+    return self.lines_along(i, &edges);
+  }
+}
+
+impl Hough {
+  // the lines through `edges`, found in `i` with the canny thresholds of `self`
+  fn lines_along(&self, i: &GrayImage, edges: &GrayImage) -> Result<Vec<Line>, SegmentError> {
+    debug::write_gray(edges, "line-canny")?;
+    let (image_width, image_height) = i.dimensions();
+
+    let lines = detect_lines(
+      edges,
+      LineDetectionOptions {
+        vote_threshold: self.vote_threshold,
+        suppression_radius: self.suppression_radius,
+      },
+    );
+
+    if crate::debug::debug_images() {
+      let mut lines_image = DynamicImage::ImageLuma8(i.clone()).to_rgb8();
+      let len = lines.len();
+      for (index, line) in lines.iter().enumerate() {
+        let v = (index as f32 / len as f32 * 255.0) as u8;
+        let mut color = Rgb::<u8>([100, v, 200]);
+        if index == 0 {
+          color = Rgb::<u8>([255, 0, 0]);
+        } else if index == len - 1 {
+          color = Rgb::<u8>([0, 255, 0]);
+        }
+        draw_polar_line(&mut lines_image, *line, color);
+      }
+      debug::write_rgb(&lines_image, "line-polar-lines")?;
+    }
+
+    let mut lines_points: Vec<Line> = Vec::new();
+    for line in lines {
+      if let Some(mut l) = polar_line_points(line, image_width, image_height) {
+        l.strength = edge_pixels_along(edges, &l) as f32;
+        lines_points.push(l);
       }
-      draw_polar_line(&mut lines_image, lines[index], color);
     }
-    debug::write_rgb(&lines_image, "line-polar-lines");
+    if lines_points.is_empty() {
+      return Err(SegmentError::NoLinesFound);
+    }
+    return Ok(lines_points);
+  }
+}
+
+// canny thresholds as fractions of the typical gradient across an edge
+const CANNY_LOW_FRACTION: f32 = 0.2;
+const CANNY_HIGH_FRACTION: f32 = 0.35;
+
+// fraction of the shorter image side a line must cover in edge pixels, on top of those it
+// passes through by chance
+const VOTE_FRACTION: f32 = 0.2;
+
+// lines closer than this fraction of the shorter image side are suppressed
+const SUPPRESSION_FRACTION: f32 = 0.05;
+
+impl Hough {
+  /// Thresholds chosen for `i`. The Canny thresholds are fractions of the median gradient
+  /// of the pixels steeper than average, which is the gradient across a typical edge, and a
+  /// line must cover a fixed fraction of the image in edge pixels on top of the ones it
+  /// crosses by chance where edges are dense.
+  pub fn adapted_to(i: &GrayImage) -> Self {
+    return Hough::adapted(i).0;
   }
 
-  let mut lines_points: Vec<Line> = Vec::new();
-  for line in lines {
-    if let Some(l) = polar_line_points(line, image_width, image_height) {
-      lines_points.push(l);
+  // the adapted thresholds along with the edges found with them
+  fn adapted(i: &GrayImage) -> (Self, GrayImage) {
+    let gradients: Vec<f32> = sobel_gradients(i).pixels().map(|p| p[0] as f32).collect();
+    let mean = gradients.iter().sum::<f32>() / gradients.len().max(1) as f32;
+    let mut steep: Vec<f32> = gradients.into_iter().filter(|g| *g > mean).collect();
+    let middle = steep.len() / 2;
+    let edge_gradient = if steep.is_empty() {
+      0.0
+    } else {
+      *steep
+        .select_nth_unstable_by(middle, |a, b| a.partial_cmp(b).unwrap())
+        .1
+    };
+    let canny_low = (CANNY_LOW_FRACTION * edge_gradient).max(1.0);
+    let canny_high = (CANNY_HIGH_FRACTION * edge_gradient).max(canny_low + 1.0);
+    let edges = canny(i, canny_low, canny_high);
+
+    let (width, height) = i.dimensions();
+    let side = width.min(height) as f32;
+    let edge_pixels = edges.pixels().filter(|p| p[0] > 0).count();
+    let density = edge_pixels as f32 / (width * height).max(1) as f32;
+    let hough = Hough {
+      canny_low,
+      canny_high,
+      vote_threshold: (side * (VOTE_FRACTION + density)).round().max(1.0) as u32,
+      suppression_radius: (side * SUPPRESSION_FRACTION).round().max(1.0) as u32,
+    };
+    return (hough, edges);
+  }
+}
+
+/// The Hough transform with thresholds chosen for every image, see `Hough::adapted_to`. When
+/// either direction of board edges has fewer than `min_lines_per_family` lines, the edge and
+/// vote thresholds are lowered by `relaxation` and the lines are looked for again, at most
+/// `max_relaxations` times. The suppression radius is kept, it follows the size of the squares
+/// rather than the contrast. Failing that the attempt that came closest is used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveHough {
+  pub min_lines_per_family: usize,
+  pub relaxation: f32,
+  pub max_relaxations: usize,
+}
+
+impl Default for AdaptiveHough {
+  fn default() -> Self {
+    AdaptiveHough {
+      min_lines_per_family: LATTICE_SIZE,
+      relaxation: 0.8,
+      max_relaxations: 4,
+    }
+  }
+}
+
+impl LineDetector for AdaptiveHough {
+  fn detect(&self, i: &GrayImage) -> Result<Vec<Line>, SegmentError> {
+    let (mut hough, mut edges) = Hough::adapted(i);
+    let mut best: Option<(usize, Vec<Line>)> = None;
+    for relaxations in 0..=self.max_relaxations {
+      if relaxations > 0 {
+        hough.canny_low *= self.relaxation;
+        hough.canny_high *= self.relaxation;
+        hough.vote_threshold =
+          ((hough.vote_threshold as f32 * self.relaxation).round() as u32).max(1);
+        edges = canny(i, hough.canny_low, hough.canny_high);
+      }
+      let lines = match hough.lines_along(i, &edges) {
+        Ok(lines) => lines,
+        Err(SegmentError::NoLinesFound) => continue,
+        Err(err) => return Err(err),
+      };
+      let found = lines_per_family(&lines);
+      if found >= self.min_lines_per_family {
+        return Ok(lines);
+      }
+      if best.as_ref().is_none_or(|(most, _)| found > *most) {
+        best = Some((found, lines));
+      }
     }
+    return best
+      .map(|(_, lines)| lines)
+      .ok_or(SegmentError::NoLinesFound);
   }
-  return lines_points;
+}
+
+// edge pixels hit when stepping a pixel at a time along `line`
+fn edge_pixels_along(edges: &GrayImage, line: &Line) -> usize {
+  let (width, height) = edges.dimensions();
+  let steps = line.length().ceil() as usize;
+  let mut count = 0;
+  for step in 0..=steps {
+    let t = if steps == 0 {
+      0.0
+    } else {
+      step as f32 / steps as f32
+    };
+    let x = line.start.0 + (line.end.0 - line.start.0) * t;
+    let y = line.start.1 + (line.end.1 - line.start.1) * t;
+    let inside = x >= 0.0 && y >= 0.0 && (x as u32) < width && (y as u32) < height;
+    if inside && edges.get_pixel(x as u32, y as u32)[0] > 0 {
+      count += 1;
+    }
+  }
+  return count;
+}
+
+// lines in the smaller of the two directions of board edges among `lines`
+fn lines_per_family(lines: &[Line]) -> usize {
+  return group_lines(lines).map_or(0, |families| {
+    families
+      .families
+      .iter()
+      .map(|family| family.len())
+      .min()
+      .unwrap_or(0)
+  });
+}
+
+#[cfg(test)]
+fn board_image(dark: u8, light: u8) -> GrayImage {
+  use crate::checkerboard::Checkerboard;
+
+  // a board of 40 pixel squares turned a little, on a background between its colours
+  let middle = ((dark as u32 + light as u32) / 2) as u8;
+  return Checkerboard {
+    dark: image::Rgb([dark; 3]),
+    light: image::Rgb([light; 3]),
+    background: image::Rgb([middle; 3]),
+    ..Checkerboard::rotated(8, 40.0, 0.1, (200.0, 200.0))
+  }
+  .gray(400, 400);
+}
+
+#[test]
+fn should_adapt_hough_thresholds_to_contrast() {
+  let strong = Hough::adapted_to(&board_image(40, 210));
+  let faint = Hough::adapted_to(&board_image(110, 140));
+  assert!(faint.canny_high < strong.canny_high / 3.0);
+  assert!(faint.canny_low < faint.canny_high);
+  assert_eq!(strong.suppression_radius, 20);
+
+  // the thresholds tuned for screenshots miss the faint board, the adapted ones do not
+  let faint_image = board_image(110, 140);
+  let fixed = Hough::default().detect(&faint_image).unwrap_or_default();
+  assert!(lines_per_family(&fixed) < LATTICE_SIZE);
+  let adapted = AdaptiveHough::default().detect(&faint_image).unwrap();
+  assert!(lines_per_family(&adapted) >= LATTICE_SIZE);
 }
@@ -0,0 +1,339 @@
+// A line segment detector after LSD (von Gioi et al.): pixels whose level lines, the lines
+// along which the intensity stays the same, point the same way are grown into regions and a
+// segment is fitted to every region long and narrow enough to be an edge. Unlike the Hough
+// transform it is not tied to whole degree angles.
+
+use crate::debug;
+use crate::error::SegmentError;
+use crate::line::{Line, LineDetector};
+use image::{DynamicImage, GrayImage, Rgb};
+use imageproc::drawing::draw_line_segment_mut;
+use imageproc::filter::gaussian_blur_f32;
+use std::f32::consts::PI;
+
+// the image is blurred this much to suppress aliasing before gradients are taken
+const BLUR_SIGMA: f32 = 0.6;
+
+// fraction of the rectangle around a region its pixels must fill, lower means curved or
+// scattered
+const MIN_DENSITY: f32 = 0.6;
+
+// segments merge when their directions differ by less than this many radians
+const MERGE_ANGLE: f32 = 0.03;
+
+/// Finds line segments by growing regions of pixels with the same gradient direction, see
+/// `LineDetector`. A chess board edge changes polarity at every corner, so gradients pointing
+/// either way along it count as the same direction and the segments of one board line are
+/// merged. The strength of a segment is the total gradient magnitude of its pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lsd {
+  /// Gradient magnitude, in grey levels per pixel, below which a pixel is not part of any
+  /// edge.
+  pub min_gradient: f32,
+
+  /// Largest angle in radians between the gradient of a pixel and that of the region it
+  /// joins.
+  pub angle_tolerance: f32,
+
+  /// Segments shorter than this many pixels are dropped.
+  pub min_length: f32,
+
+  /// Collinear segments closer than this many pixels end to end are merged.
+  pub max_gap: f32,
+}
+
+impl Default for Lsd {
+  fn default() -> Self {
+    Lsd {
+      min_gradient: 10.0,
+      angle_tolerance: PI / 8.0,
+      min_length: 15.0,
+      max_gap: 20.0,
+    }
+  }
+}
+
+// gradient magnitude and level line angle in [0, pi) of every pixel, taken between the pixel
+// and its neighbours to the right and below, so both refer to the centre of the four
+struct Gradients {
+  width: usize,
+  height: usize,
+  magnitude: Vec<f32>,
+  angle: Vec<f32>,
+}
+
+impl Gradients {
+  fn of(i: &GrayImage) -> Self {
+    let (width, height) = i.dimensions();
+    let (width, height) = (
+      width.saturating_sub(1) as usize,
+      height.saturating_sub(1) as usize,
+    );
+    let mut magnitude = vec![0.0; width * height];
+    let mut angle = vec![0.0; width * height];
+    let pixel = |x: usize, y: usize| i.get_pixel(x as u32, y as u32)[0] as f32;
+    for y in 0..height {
+      for x in 0..width {
+        let (a, b) = (pixel(x, y), pixel(x + 1, y));
+        let (c, d) = (pixel(x, y + 1), pixel(x + 1, y + 1));
+        let gx = (b + d - a - c) / 2.0;
+        let gy = (c + d - a - b) / 2.0;
+        magnitude[y * width + x] = (gx * gx + gy * gy).sqrt();
+        angle[y * width + x] = gx.atan2(-gy).rem_euclid(PI);
+      }
+    }
+    return Gradients {
+      width,
+      height,
+      magnitude,
+      angle,
+    };
+  }
+}
+
+// difference between two directions in [0, pi)
+fn angle_difference(a: f32, b: f32) -> f32 {
+  let difference = (a - b).abs();
+  return difference.min(PI - difference);
+}
+
+impl Lsd {
+  // the pixels reached from `seed` whose level lines run along the region, marking them used
+  fn grow(&self, gradients: &Gradients, used: &mut [bool], seed: usize) -> Vec<usize> {
+    let mut region = vec![seed];
+    used[seed] = true;
+    // directions are summed doubled so that opposite ones add up instead of cancelling
+    let doubled = |angle: f32| ((2.0 * angle).cos(), (2.0 * angle).sin());
+    let (mut sum_cos, mut sum_sin) = doubled(gradients.angle[seed]);
+    let mut region_angle = gradients.angle[seed];
+
+    let mut next = 0;
+    while next < region.len() {
+      let (x, y) = (
+        region[next] % gradients.width,
+        region[next] / gradients.width,
+      );
+      next += 1;
+      for dy in -1i32..=1 {
+        for dx in -1i32..=1 {
+          let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+          if nx < 0 || ny < 0 || nx as usize >= gradients.width || ny as usize >= gradients.height {
+            continue;
+          }
+          let neighbour = ny as usize * gradients.width + nx as usize;
+          if used[neighbour]
+            || gradients.magnitude[neighbour] < self.min_gradient
+            || angle_difference(gradients.angle[neighbour], region_angle) > self.angle_tolerance
+          {
+            continue;
+          }
+          used[neighbour] = true;
+          region.push(neighbour);
+          let (c, s) = doubled(gradients.angle[neighbour]);
+          sum_cos += c;
+          sum_sin += s;
+          region_angle = (sum_sin.atan2(sum_cos) / 2.0).rem_euclid(PI);
+        }
+      }
+    }
+    return region;
+  }
+
+  // the segment through the middle of `region` along its main axis, `None` when the region is
+  // too short or not narrow and full enough to be a straight edge
+  fn fit(&self, gradients: &Gradients, region: &[usize]) -> Option<Line> {
+    let position = |index: usize| {
+      (
+        (index % gradients.width) as f32 + 0.5,
+        (index / gradients.width) as f32 + 0.5,
+      )
+    };
+    let total: f32 = region.iter().map(|i| gradients.magnitude[*i]).sum();
+    let (mut cx, mut cy) = (0.0, 0.0);
+    for index in region.iter() {
+      let (x, y) = position(*index);
+      cx += x * gradients.magnitude[*index] / total;
+      cy += y * gradients.magnitude[*index] / total;
+    }
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for index in region.iter() {
+      let (x, y) = position(*index);
+      let weight = gradients.magnitude[*index];
+      sxx += weight * (x - cx) * (x - cx);
+      syy += weight * (y - cy) * (y - cy);
+      sxy += weight * (x - cx) * (y - cy);
+    }
+    let angle = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+    let (sin, cos) = angle.sin_cos();
+
+    let (mut along_min, mut along_max) = (f32::INFINITY, f32::NEG_INFINITY);
+    let (mut across_min, mut across_max) = (f32::INFINITY, f32::NEG_INFINITY);
+    for index in region.iter() {
+      let (x, y) = position(*index);
+      let along = (x - cx) * cos + (y - cy) * sin;
+      let across = (y - cy) * cos - (x - cx) * sin;
+      along_min = along_min.min(along);
+      along_max = along_max.max(along);
+      across_min = across_min.min(across);
+      across_max = across_max.max(across);
+    }
+    let length = along_max - along_min;
+    let width = (across_max - across_min).max(1.0);
+    if length < self.min_length || (region.len() as f32) < MIN_DENSITY * length * width {
+      return None;
+    }
+
+    return Some(Line {
+      start: (cx + along_min * cos, cy + along_min * sin),
+      end: (cx + along_max * cos, cy + along_max * sin),
+      strength: total,
+    });
+  }
+}
+
+// whether `segment` continues `line` within `max_gap` pixels
+fn continues(line: &Line, segment: &Line, max_gap: f32) -> bool {
+  if angle_difference(line.angle(), segment.angle()) > MERGE_ANGLE {
+    return false;
+  }
+  let (sin, cos) = line.angle().sin_cos();
+  let along = |p: (f32, f32)| (p.0 - line.start.0) * cos + (p.1 - line.start.1) * sin;
+  let across = |p: (f32, f32)| ((p.1 - line.start.1) * cos - (p.0 - line.start.0) * sin).abs();
+  let (start, end) = (along(line.start), along(line.end));
+  let (low, high) = (start.min(end), start.max(end));
+  let (a, b) = (along(segment.start), along(segment.end));
+  let gap = (a.min(b) - high).max(low - a.max(b)).max(0.0);
+  // the further the segment reaches, the more a small difference in angle moves its ends
+  let offset = across(segment.start).max(across(segment.end));
+  return gap <= max_gap && offset <= 1.0 + (gap + segment.length()) * MERGE_ANGLE;
+}
+
+// the segment covering both `a` and `b`, along their directions and through their middles
+// weighted by strength
+fn join(a: &Line, b: &Line) -> Line {
+  let strength = a.strength + b.strength;
+  let weight = if strength > 0.0 {
+    a.strength / strength
+  } else {
+    0.5
+  };
+  // directions are averaged doubled so that opposite ones agree
+  let doubled = |line: &Line| ((2.0 * line.angle()).cos(), (2.0 * line.angle()).sin());
+  let (ac, as_) = doubled(a);
+  let (bc, bs) = doubled(b);
+  let angle = (weight * as_ + (1.0 - weight) * bs).atan2(weight * ac + (1.0 - weight) * bc) / 2.0;
+  let (sin, cos) = angle.sin_cos();
+  let middle = |line: &Line| {
+    (
+      (line.start.0 + line.end.0) / 2.0,
+      (line.start.1 + line.end.1) / 2.0,
+    )
+  };
+  let (ma, mb) = (middle(a), middle(b));
+  let centre = (
+    weight * ma.0 + (1.0 - weight) * mb.0,
+    weight * ma.1 + (1.0 - weight) * mb.1,
+  );
+
+  let along = |p: &(f32, f32)| (p.0 - centre.0) * cos + (p.1 - centre.1) * sin;
+  let ends = [a.start, a.end, b.start, b.end];
+  let low = ends.iter().map(along).fold(f32::INFINITY, f32::min);
+  let high = ends.iter().map(along).fold(f32::NEG_INFINITY, f32::max);
+  return Line {
+    start: (centre.0 + low * cos, centre.1 + low * sin),
+    end: (centre.0 + high * cos, centre.1 + high * sin),
+    strength,
+  };
+}
+
+// joins segments lying along the same line into segments spanning them, until no two are left
+// that continue each other
+fn merge(mut segments: Vec<Line>, max_gap: f32) -> Vec<Line> {
+  loop {
+    segments.sort_by(|a, b| b.strength.partial_cmp(&a.strength).unwrap());
+    let count = segments.len();
+    let mut merged: Vec<Line> = Vec::new();
+    for segment in segments {
+      match merged
+        .iter_mut()
+        .find(|line| continues(line, &segment, max_gap))
+      {
+        Some(line) => *line = join(line, &segment),
+        None => merged.push(segment),
+      }
+    }
+    segments = merged;
+    if segments.len() == count {
+      return segments;
+    }
+  }
+}
+
+impl LineDetector for Lsd {
+  fn detect(&self, i: &GrayImage) -> Result<Vec<Line>, SegmentError> {
+    let gradients = Gradients::of(&gaussian_blur_f32(i, BLUR_SIGMA));
+
+    // regions are seeded at the strongest gradients first, which lie in the middle of edges
+    let mut seeds: Vec<usize> = (0..gradients.magnitude.len())
+      .filter(|index| gradients.magnitude[*index] >= self.min_gradient)
+      .collect();
+    seeds.sort_by(|a, b| {
+      gradients.magnitude[*b]
+        .partial_cmp(&gradients.magnitude[*a])
+        .unwrap()
+    });
+
+    let mut used = vec![false; gradients.magnitude.len()];
+    let mut segments = Vec::new();
+    for seed in seeds {
+      if used[seed] {
+        continue;
+      }
+      let region = self.grow(&gradients, &mut used, seed);
+      if let Some(segment) = self.fit(&gradients, &region) {
+        segments.push(segment);
+      }
+    }
+    let lines = merge(segments, self.max_gap);
+
+    if debug::debug_images() {
+      let mut lines_image = DynamicImage::ImageLuma8(i.clone()).to_rgb8();
+      for line in lines.iter() {
+        draw_line_segment_mut(&mut lines_image, line.start, line.end, Rgb([255, 0, 0]));
+      }
+      debug::write_rgb(&lines_image, "line-segments")?;
+    }
+
+    if lines.is_empty() {
+      return Err(SegmentError::NoLinesFound);
+    }
+    return Ok(lines);
+  }
+}
+
+#[test]
+fn should_detect_segments_at_odd_angles() {
+  use crate::checkerboard::Checkerboard;
+
+  // a board of 40 pixel squares turned by an angle well off the whole degrees
+  let angle = 0.3f32;
+  let image = Checkerboard {
+    samples: 4,
+    ..Checkerboard::rotated(4, 40.0, angle, (150.0, 150.0))
+  }
+  .gray(300, 300);
+
+  let lines = Lsd::default().detect(&image).unwrap();
+  // every board line is found whole, across the polarity changes at the corners, at the angle
+  // of the board to within a tenth of a degree
+  let full: Vec<&Line> = lines.iter().filter(|line| line.length() > 150.0).collect();
+  assert_eq!(full.len(), 10, "{:?}", lines);
+  for line in full.iter() {
+    let error =
+      angle_difference(line.angle(), angle).min(angle_difference(line.angle(), angle + PI / 2.0));
+    assert!(error < 0.1f32.to_radians(), "{:?}", line);
+  }
+  assert!(lines
+    .windows(2)
+    .all(|pair| pair[0].strength >= pair[1].strength));
+}
@@ -0,0 +1,653 @@
+use crate::pieces::{Piece, PieceColor, PieceKind};
+use crate::position::{CastlingRights, Position};
+use crate::square::{Square, BOARD_WIDTH};
+use std::error::Error;
+use std::fmt;
+
+const KNIGHT_OFFSETS: [(i8, i8); 8] = [
+  (1, 2),
+  (2, 1),
+  (2, -1),
+  (1, -2),
+  (-1, -2),
+  (-2, -1),
+  (-2, 1),
+  (-1, 2),
+];
+const KING_OFFSETS: [(i8, i8); 8] = [
+  (0, 1),
+  (1, 1),
+  (1, 0),
+  (1, -1),
+  (0, -1),
+  (-1, -1),
+  (-1, 0),
+  (-1, 1),
+];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const PROMOTIONS: [PieceKind; 4] = [
+  PieceKind::Queen,
+  PieceKind::Rook,
+  PieceKind::Bishop,
+  PieceKind::Knight,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MoveKind {
+  Normal,
+  EnPassant,
+  KingSideCastle,
+  QueenSideCastle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+  pub piece: Piece,
+  pub from: Square,
+  pub to: Square,
+  pub kind: MoveKind,
+
+  /// The piece taken, including the pawn removed by en passant.
+  pub captured: Option<Piece>,
+
+  pub promotion: Option<PieceKind>,
+}
+
+impl Move {
+  fn new(piece: Piece, from: Square, to: Square, captured: Option<Piece>) -> Self {
+    Move {
+      piece,
+      from,
+      to,
+      kind: MoveKind::Normal,
+      captured,
+      promotion: None,
+    }
+  }
+}
+
+/// Long algebraic notation as used by UCI, e.g. `e2e4` or `e7e8q`.
+impl fmt::Display for Move {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}{}", self.from, self.to)?;
+    if let Some(kind) = self.promotion {
+      write!(f, "{}", Piece::new(PieceColor::Black, kind).to_char())?;
+    }
+    return Ok(());
+  }
+}
+
+/// Reasons the difference between two positions could not be explained by a single move.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmbiguousDiff {
+  /// Both snapshots have the same pieces on the same squares.
+  NoChange,
+
+  /// No legal move of the side to move leads to the new snapshot. Holds the squares whose
+  /// contents differ.
+  NoLegalMove(Vec<Square>),
+
+  /// Several legal moves explain the difference equally well.
+  MultipleMoves(Vec<Move>),
+}
+
+impl fmt::Display for AmbiguousDiff {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AmbiguousDiff::NoChange => write!(f, "the board did not change"),
+      AmbiguousDiff::NoLegalMove(changed) => {
+        let names: Vec<String> = changed.iter().map(|s| s.to_string()).collect();
+        write!(f, "no legal move changes {}", names.join(", "))
+      }
+      AmbiguousDiff::MultipleMoves(moves) => {
+        let names: Vec<String> = moves.iter().map(|m| m.to_string()).collect();
+        write!(f, "the change matches several moves: {}", names.join(", "))
+      }
+    }
+  }
+}
+
+impl Error for AmbiguousDiff {}
+
+fn home_rank(color: PieceColor) -> u8 {
+  return match color {
+    PieceColor::White => 0,
+    PieceColor::Black => BOARD_WIDTH - 1,
+  };
+}
+
+fn forward(color: PieceColor) -> i8 {
+  return match color {
+    PieceColor::White => 1,
+    PieceColor::Black => -1,
+  };
+}
+
+// a king or rook leaving or a rook being captured on its starting square ends castling on
+// that side
+fn revoke_castling(rights: &mut CastlingRights, square: Square) {
+  match (square.file(), square.rank()) {
+    (0, 0) => rights.white_queen_side = false,
+    (7, 0) => rights.white_king_side = false,
+    (4, 0) => {
+      rights.white_king_side = false;
+      rights.white_queen_side = false;
+    }
+    (0, 7) => rights.black_queen_side = false,
+    (7, 7) => rights.black_king_side = false,
+    (4, 7) => {
+      rights.black_king_side = false;
+      rights.black_queen_side = false;
+    }
+    _ => {}
+  }
+}
+
+impl Position {
+  pub fn king(&self, color: PieceColor) -> Option<Square> {
+    return Square::all().find(|s| self.piece(*s) == Some(Piece::new(color, PieceKind::King)));
+  }
+
+  /// Whether any piece of colour `by` attacks `square`.
+  pub fn is_attacked(&self, square: Square, by: PieceColor) -> bool {
+    let is = |target: Option<Square>, kinds: &[PieceKind]| {
+      let piece = target.and_then(|s| self.piece(s));
+      piece.is_some_and(|p| p.color == by && kinds.contains(&p.kind))
+    };
+
+    let behind = -forward(by);
+    if is(square.offset(-1, behind), &[PieceKind::Pawn])
+      || is(square.offset(1, behind), &[PieceKind::Pawn])
+    {
+      return true;
+    }
+    for (files, ranks) in KNIGHT_OFFSETS.iter() {
+      if is(square.offset(*files, *ranks), &[PieceKind::Knight]) {
+        return true;
+      }
+    }
+    for (files, ranks) in KING_OFFSETS.iter() {
+      if is(square.offset(*files, *ranks), &[PieceKind::King]) {
+        return true;
+      }
+    }
+
+    let sliders = [
+      (&BISHOP_DIRECTIONS, PieceKind::Bishop),
+      (&ROOK_DIRECTIONS, PieceKind::Rook),
+    ];
+    for (directions, kind) in sliders.iter() {
+      for (files, ranks) in directions.iter() {
+        let mut next = square.offset(*files, *ranks);
+        while let Some(target) = next {
+          if self.piece(target).is_some() {
+            if is(next, &[*kind, PieceKind::Queen]) {
+              return true;
+            }
+            break;
+          }
+          next = target.offset(*files, *ranks);
+        }
+      }
+    }
+    return false;
+  }
+
+  /// Whether the side to move is in check.
+  pub fn in_check(&self) -> bool {
+    return match self.king(self.side_to_move) {
+      Some(king) => self.is_attacked(king, self.side_to_move.opposite()),
+      None => false,
+    };
+  }
+
+  fn pawn_moves(&self, piece: Piece, from: Square, moves: &mut Vec<Move>) {
+    let color = piece.color;
+    let step = forward(color);
+    let last_rank = home_rank(color.opposite());
+    let mut push = |mv: Move| {
+      if mv.to.rank() == last_rank {
+        for kind in PROMOTIONS.iter() {
+          moves.push(Move {
+            promotion: Some(*kind),
+            ..mv
+          });
+        }
+      } else {
+        moves.push(mv);
+      }
+    };
+
+    if let Some(to) = from.offset(0, step).filter(|s| self.piece(*s).is_none()) {
+      push(Move::new(piece, from, to, None));
+      let start_rank = (home_rank(color) as i8 + step) as u8;
+      if let Some(to) = from.offset(0, 2 * step) {
+        if from.rank() == start_rank && self.piece(to).is_none() {
+          push(Move::new(piece, from, to, None));
+        }
+      }
+    }
+
+    for files in [-1, 1].iter() {
+      let to = match from.offset(*files, step) {
+        Some(to) => to,
+        None => continue,
+      };
+      match self.piece(to) {
+        Some(target) if target.color != color => push(Move::new(piece, from, to, Some(target))),
+        None if self.en_passant == Some(to) => push(Move {
+          kind: MoveKind::EnPassant,
+          ..Move::new(
+            piece,
+            from,
+            to,
+            Some(Piece::new(color.opposite(), PieceKind::Pawn)),
+          )
+        }),
+        _ => {}
+      }
+    }
+  }
+
+  fn castling_moves(&self, king: Piece, from: Square, moves: &mut Vec<Move>) {
+    let color = king.color;
+    let rank = home_rank(color);
+    if from != Square::new(4, rank).unwrap() || self.is_attacked(from, color.opposite()) {
+      return;
+    }
+    let (king_side, queen_side) = match color {
+      PieceColor::White => (
+        self.castling.white_king_side,
+        self.castling.white_queen_side,
+      ),
+      PieceColor::Black => (
+        self.castling.black_king_side,
+        self.castling.black_queen_side,
+      ),
+    };
+    // (allowed, rook file, squares that must be empty, squares the king crosses, kind)
+    let sides = [
+      (
+        king_side,
+        7,
+        &[5, 6][..],
+        &[5, 6][..],
+        MoveKind::KingSideCastle,
+      ),
+      (
+        queen_side,
+        0,
+        &[1, 2, 3][..],
+        &[3, 2][..],
+        MoveKind::QueenSideCastle,
+      ),
+    ];
+    for (allowed, rook_file, empty, crossed, kind) in sides.iter() {
+      let rook = Square::new(*rook_file, rank).and_then(|s| self.piece(s));
+      if !allowed || rook != Some(Piece::new(color, PieceKind::Rook)) {
+        continue;
+      }
+      let square = |file: &u8| Square::new(*file, rank).unwrap();
+      if empty.iter().any(|f| self.piece(square(f)).is_some())
+        || crossed
+          .iter()
+          .any(|f| self.is_attacked(square(f), color.opposite()))
+      {
+        continue;
+      }
+      moves.push(Move {
+        kind: *kind,
+        ..Move::new(king, from, square(&crossed[1]), None)
+      });
+    }
+  }
+
+  // moves that follow the movement rules but may leave the king in check
+  fn pseudo_legal_moves(&self) -> Vec<Move> {
+    let mut moves = Vec::new();
+    for (from, piece) in self.pieces() {
+      if piece.color != self.side_to_move {
+        continue;
+      }
+
+      let (directions, sliding): (&[(i8, i8)], bool) = match piece.kind {
+        PieceKind::Pawn => {
+          self.pawn_moves(piece, from, &mut moves);
+          continue;
+        }
+        PieceKind::Knight => (&KNIGHT_OFFSETS, false),
+        PieceKind::Bishop => (&BISHOP_DIRECTIONS, true),
+        PieceKind::Rook => (&ROOK_DIRECTIONS, true),
+        PieceKind::Queen => (&KING_OFFSETS, true),
+        PieceKind::King => {
+          self.castling_moves(piece, from, &mut moves);
+          (&KING_OFFSETS, false)
+        }
+      };
+      for (files, ranks) in directions.iter() {
+        let mut next = from.offset(*files, *ranks);
+        while let Some(to) = next {
+          match self.piece(to) {
+            Some(target) => {
+              if target.color != piece.color {
+                moves.push(Move::new(piece, from, to, Some(target)));
+              }
+              break;
+            }
+            None => moves.push(Move::new(piece, from, to, None)),
+          }
+          next = if sliding {
+            to.offset(*files, *ranks)
+          } else {
+            None
+          };
+        }
+      }
+    }
+    return moves;
+  }
+
+  /// Every legal move of the side to move. Positions without a king of that colour are
+  /// allowed, in which case check is ignored.
+  pub fn legal_moves(&self) -> Vec<Move> {
+    let color = self.side_to_move;
+    return self
+      .pseudo_legal_moves()
+      .into_iter()
+      .filter(|mv| {
+        let after = self.play(mv);
+        match after.king(color) {
+          Some(king) => !after.is_attacked(king, color.opposite()),
+          None => true,
+        }
+      })
+      .collect();
+  }
+
+  /// The position after `mv`, which is assumed to be legal. Castling rights, en passant,
+  /// the move counters and the side to move are updated.
+  pub fn play(&self, mv: &Move) -> Position {
+    let mut next = self.clone();
+    let placed = match mv.promotion {
+      Some(kind) => Piece::new(mv.piece.color, kind),
+      None => mv.piece,
+    };
+    next.set_piece(mv.from, None);
+    next.set_piece(mv.to, Some(placed));
+
+    let rank = mv.from.rank();
+    let rook = match mv.kind {
+      MoveKind::Normal => None,
+      MoveKind::EnPassant => {
+        next.set_piece(Square::new(mv.to.file(), rank).unwrap(), None);
+        None
+      }
+      MoveKind::KingSideCastle => Some((7, 5)),
+      MoveKind::QueenSideCastle => Some((0, 3)),
+    };
+    if let Some((from_file, to_file)) = rook {
+      let piece = next.piece(Square::new(from_file, rank).unwrap());
+      next.set_piece(Square::new(from_file, rank).unwrap(), None);
+      next.set_piece(Square::new(to_file, rank).unwrap(), piece);
+    }
+
+    revoke_castling(&mut next.castling, mv.from);
+    revoke_castling(&mut next.castling, mv.to);
+
+    let is_pawn = mv.piece.kind == PieceKind::Pawn;
+    next.en_passant = None;
+    if is_pawn && (mv.to.rank() as i8 - rank as i8).abs() == 2 {
+      next.en_passant = Square::new(mv.from.file(), (rank + mv.to.rank()) / 2);
+    }
+    if is_pawn || mv.captured.is_some() {
+      next.halfmove_clock = 0;
+    } else {
+      next.halfmove_clock += 1;
+    }
+    if self.side_to_move == PieceColor::Black {
+      next.fullmove_number += 1;
+    }
+    next.side_to_move = self.side_to_move.opposite();
+    return next;
+  }
+
+  /// Standard algebraic notation of the legal move `mv`, e.g. `Nbd7`, `exd6`, `e8=Q+` or
+  /// `O-O-O#`.
+  pub fn san(&self, mv: &Move) -> String {
+    let mut san = match mv.kind {
+      MoveKind::KingSideCastle => "O-O".to_string(),
+      MoveKind::QueenSideCastle => "O-O-O".to_string(),
+      _ if mv.piece.kind == PieceKind::Pawn => {
+        let mut san = String::new();
+        if mv.captured.is_some() {
+          san.push(mv.from.to_string().as_bytes()[0] as char);
+          san.push('x');
+        }
+        san.push_str(&mv.to.to_string());
+        if let Some(kind) = mv.promotion {
+          san.push('=');
+          san.push(Piece::new(PieceColor::White, kind).to_char());
+        }
+        san
+      }
+      _ => {
+        let mut san = Piece::new(PieceColor::White, mv.piece.kind)
+          .to_char()
+          .to_string();
+        // name the origin file, rank or both when another piece of the same kind could also
+        // reach the target square
+        let rivals: Vec<Square> = self
+          .legal_moves()
+          .iter()
+          .filter(|m| m.piece == mv.piece && m.to == mv.to && m.from != mv.from)
+          .map(|m| m.from)
+          .collect();
+        let from = mv.from.to_string();
+        if !rivals.is_empty() {
+          if rivals.iter().all(|s| s.file() != mv.from.file()) {
+            san.push_str(&from[..1]);
+          } else if rivals.iter().all(|s| s.rank() != mv.from.rank()) {
+            san.push_str(&from[1..]);
+          } else {
+            san.push_str(&from);
+          }
+        }
+        if mv.captured.is_some() {
+          san.push('x');
+        }
+        san.push_str(&mv.to.to_string());
+        san
+      }
+    };
+
+    let after = self.play(mv);
+    if after.in_check() {
+      san.push(if after.legal_moves().is_empty() { '#' } else { '+' });
+    }
+    return san;
+  }
+}
+
+// picks the legal move whose result `mismatch` scores lowest, `None` scores rule a move out
+fn best_move<F>(prev: &Position, changed: Vec<Square>, mismatch: F) -> Result<Move, AmbiguousDiff>
+where
+  F: Fn(&Move, &Position) -> Option<usize>,
+{
+  if changed.is_empty() {
+    return Err(AmbiguousDiff::NoChange);
+  }
+
+  let mut best: Vec<Move> = Vec::new();
+  let mut best_score = usize::MAX;
+  for mv in prev.legal_moves() {
+    let score = match mismatch(&mv, &prev.play(&mv)) {
+      Some(score) => score,
+      None => continue,
+    };
+    if score < best_score {
+      best.clear();
+      best_score = score;
+    }
+    if score == best_score {
+      best.push(mv);
+    }
+  }
+
+  return match best.len() {
+    0 => Err(AmbiguousDiff::NoLegalMove(changed)),
+    1 => Ok(best[0]),
+    _ => Err(AmbiguousDiff::MultipleMoves(best)),
+  };
+}
+
+/// Finds the legal move of `prev.side_to_move` that turns `prev` into `next`.
+///
+/// Only the piece placement of `next` is compared. Every square must hold a piece of the same
+/// colour as after the move, while piece kinds only break ties so that an occasional
+/// misclassified piece does not prevent a move from being found.
+pub fn infer_move(prev: &Position, next: &Position) -> Result<Move, AmbiguousDiff> {
+  let changed = Square::all()
+    .filter(|s| prev.piece(*s) != next.piece(*s))
+    .collect();
+  return best_move(prev, changed, |_, after| {
+    let mut mismatch = 0;
+    for square in Square::all() {
+      let (expected, seen) = (after.piece(square), next.piece(square));
+      if expected.map(|p| p.color) != seen.map(|p| p.color) {
+        return None;
+      }
+      if expected != seen {
+        mismatch += 1;
+      }
+    }
+    Some(mismatch)
+  });
+}
+
+/// Like `infer_move` when only the occupancy of the new board is known, with bit
+/// `Square::index` set for every occupied square. Pawns are assumed to promote to a queen.
+pub fn infer_move_from_occupancy(prev: &Position, next: u64) -> Result<Move, AmbiguousDiff> {
+  let changed = Square::all()
+    .filter(|s| prev.occupancy() & (1 << s.index()) != next & (1 << s.index()))
+    .collect();
+  return best_move(prev, changed, |mv, after| {
+    let underpromotion = mv.promotion.is_some() && mv.promotion != Some(PieceKind::Queen);
+    if underpromotion || after.occupancy() != next {
+      return None;
+    }
+    Some(0)
+  });
+}
+
+#[cfg(test)]
+fn play_uci(position: &Position, uci: &str) -> Position {
+  let mv = position
+    .legal_moves()
+    .into_iter()
+    .find(|m| m.to_string() == uci)
+    .unwrap_or_else(|| panic!("{} is not legal", uci));
+  return position.play(&mv);
+}
+
+#[test]
+fn should_generate_legal_moves() {
+  assert_eq!(Position::starting().legal_moves().len(), 20);
+
+  // the knight on d2 is pinned against the king
+  let position = Position::from_fen("3rk3/8/8/8/8/8/3N4/3K4 w - - 0 1").unwrap();
+  assert!(position
+    .legal_moves()
+    .iter()
+    .all(|m| m.piece.kind == PieceKind::King));
+
+  let position = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+  let castles = position
+    .legal_moves()
+    .iter()
+    .filter(|m| m.kind != MoveKind::Normal)
+    .count();
+  assert_eq!(castles, 2);
+
+  let position = play_uci(&play_uci(&Position::starting(), "e2e4"), "e7e5");
+  assert_eq!(
+    position.to_fen(),
+    "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+  );
+}
+
+#[test]
+fn should_infer_moves() {
+  let cases = [
+    (
+      "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+      "g1f3",
+    ),
+    (
+      "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+      "e4d5",
+    ),
+    ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8"),
+    ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6"),
+    ("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8n"),
+  ];
+  for (fen, uci) in cases.iter() {
+    let prev = Position::from_fen(fen).unwrap();
+    let next = play_uci(&prev, uci);
+    let mv = infer_move(&prev, &next).unwrap();
+    assert_eq!(mv.to_string(), *uci);
+    assert_eq!(prev.play(&mv), next);
+  }
+
+  let prev = Position::starting();
+  assert_eq!(infer_move(&prev, &prev), Err(AmbiguousDiff::NoChange));
+  let mut teleported = prev.clone();
+  teleported.set_piece("e2".parse().unwrap(), None);
+  teleported.set_piece("e5".parse().unwrap(), prev.piece("e2".parse().unwrap()));
+  assert!(matches!(
+    infer_move(&prev, &teleported),
+    Err(AmbiguousDiff::NoLegalMove(_))
+  ));
+}
+
+#[test]
+fn should_infer_moves_from_occupancy() {
+  let prev = Position::starting();
+  let next = play_uci(&prev, "e2e4");
+  assert_eq!(
+    infer_move_from_occupancy(&prev, next.occupancy())
+      .unwrap()
+      .to_string(),
+    "e2e4"
+  );
+
+  // taking on d8 or a1 only empties d1
+  let prev = Position::from_fen("3qk3/8/8/8/8/8/8/n2RK3 w - - 0 1").unwrap();
+  let next = play_uci(&prev, "d1d8");
+  match infer_move_from_occupancy(&prev, next.occupancy()) {
+    Err(AmbiguousDiff::MultipleMoves(moves)) => assert_eq!(moves.len(), 2),
+    other => panic!("expected an ambiguous diff, got {:?}", other),
+  }
+}
+
+#[test]
+fn should_write_san() {
+  let cases = [
+    ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "g1f3", "Nf3"),
+    ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6", "exd6"),
+    ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8", "O-O-O"),
+    ("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q", "a8=Q+"),
+    ("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1", "Rad1"),
+    ("4k3/8/8/8/R7/8/8/R3K3 w - - 0 1", "a1a2", "R1a2"),
+    ("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8#"),
+  ];
+  for (fen, uci, san) in cases.iter() {
+    let position = Position::from_fen(fen).unwrap();
+    let mv = position
+      .legal_moves()
+      .into_iter()
+      .find(|m| m.to_string() == *uci)
+      .unwrap();
+    assert_eq!(position.san(&mv), *san);
+  }
+}
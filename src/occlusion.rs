@@ -0,0 +1,231 @@
+// Decides whether a rectified board can be trusted by comparing it with the last board that
+// stayed still, so that hands reaching over the board are not mistaken for moves.
+
+use crate::occupancy::color_distance;
+use image::imageops::{resize, FilterType};
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use imageproc::region_labelling::{connected_components, Connectivity};
+
+// boards are compared at this size, which hides small misalignment between frames
+const ANALYSIS_SIZE: u32 = 64;
+
+// colour distance for a pixel to count as changed
+const CHANGE_DISTANCE: f32 = 40.0;
+
+// fraction of pixels that may change between consecutive frames while the board is still
+const MOTION_FRACTION: f32 = 0.01;
+
+// a single changed region larger than this many squares cannot be explained by moving pieces.
+// The reference is renewed every time the board settles, so this only has to tell a hand from
+// the one move played since
+const OCCLUDED_BLOB_SQUARES: f32 = 2.0;
+
+// fraction of changed, skin coloured pixels above which a hand is assumed to be in view
+const SKIN_FRACTION: f32 = 0.03;
+
+const DEFAULT_SETTLE_FRAMES: usize = 3;
+
+/// What a frame shows relative to the last settled board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameState {
+  /// Nothing has changed for long enough that the board can be read.
+  Stable,
+
+  /// Something large, usually a hand, covers part of the board.
+  Occluded,
+
+  /// The board is changing or has not been still for long enough yet.
+  Moving,
+}
+
+// the classic explicit rgb skin rule, see Kovac et al. "Human skin colour clustering for face
+// detection"
+fn is_skin(p: &Rgb<u8>) -> bool {
+  let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+  let spread = r.max(g).max(b) - r.min(g).min(b);
+  return r > 95 && g > 40 && b > 20 && spread > 15 && (r - g).abs() > 15 && r > g && r > b;
+}
+
+// pixels of `a` that differ from `b`
+fn change_mask(a: &RgbImage, b: &RgbImage) -> GrayImage {
+  return GrayImage::from_fn(a.width(), a.height(), |x, y| {
+    let q = b.get_pixel(x, y);
+    let q = [q[0] as f32, q[1] as f32, q[2] as f32];
+    if color_distance(a.get_pixel(x, y), &q) > CHANGE_DISTANCE {
+      Luma([255])
+    } else {
+      Luma([0])
+    }
+  });
+}
+
+fn changed_fraction(mask: &GrayImage) -> f32 {
+  let changed = mask.pixels().filter(|p| p[0] > 0).count();
+  return changed as f32 / (mask.width() * mask.height()) as f32;
+}
+
+// number of pixels in the largest 8-connected changed region
+fn largest_blob(mask: &GrayImage) -> usize {
+  let labels = connected_components(mask, Connectivity::Eight, Luma([0]));
+  let mut sizes = std::collections::HashMap::new();
+  for label in labels.pixels().filter(|l| l[0] > 0) {
+    *sizes.entry(label[0]).or_insert(0) += 1;
+  }
+  return sizes.values().copied().max().unwrap_or(0);
+}
+
+/// Classifies a stream of rectified boards as stable, occluded or moving.
+#[derive(Clone, Debug)]
+pub struct OcclusionDetector {
+  reference: Option<RgbImage>,
+  previous: Option<RgbImage>,
+  still_frames: usize,
+  settle_frames: usize,
+}
+
+impl Default for OcclusionDetector {
+  fn default() -> Self {
+    OcclusionDetector::new()
+  }
+}
+
+impl OcclusionDetector {
+  pub fn new() -> Self {
+    OcclusionDetector {
+      reference: None,
+      previous: None,
+      still_frames: 0,
+      settle_frames: DEFAULT_SETTLE_FRAMES,
+    }
+  }
+
+  /// Number of consecutive unchanged frames after which the board counts as stable.
+  pub fn with_settle_frames(mut self, frames: usize) -> Self {
+    self.settle_frames = frames.max(1);
+    return self;
+  }
+
+  /// The last board that was reported stable, at analysis resolution.
+  pub fn reference(&self) -> Option<&RgbImage> {
+    return self.reference.as_ref();
+  }
+
+  /// Classifies the next rectified board of the stream, such as `BoardSegmentation::board`.
+  ///
+  /// The board settles once it has not moved for `settle_frames` frames with no hand in view,
+  /// and the settled frame becomes the new reference however much it differs from the old one,
+  /// so that castling, a shift in the lighting or a nudged camera are taken in rather than
+  /// reported as occluded for good.
+  pub fn update(&mut self, board: &RgbImage) -> FrameState {
+    let current = resize(board, ANALYSIS_SIZE, ANALYSIS_SIZE, FilterType::Triangle);
+    let pixels = (ANALYSIS_SIZE * ANALYSIS_SIZE) as f32;
+
+    let (hand, large) = match &self.reference {
+      Some(reference) => {
+        let changed = change_mask(&current, reference);
+        let skin = changed
+          .enumerate_pixels()
+          .filter(|(x, y, p)| p[0] > 0 && is_skin(current.get_pixel(*x, *y)))
+          .count();
+        let square_area = pixels / 64.0;
+        (
+          skin as f32 > SKIN_FRACTION * pixels,
+          largest_blob(&changed) as f32 > OCCLUDED_BLOB_SQUARES * square_area,
+        )
+      }
+      None => (false, false),
+    };
+    let moved = match &self.previous {
+      Some(previous) => changed_fraction(&change_mask(&current, previous)) > MOTION_FRACTION,
+      None => true,
+    };
+    self.previous = Some(current);
+
+    let occluded = hand || large;
+    if hand || moved {
+      self.still_frames = 0;
+    } else {
+      self.still_frames += 1;
+    }
+    if self.still_frames < self.settle_frames {
+      return if occluded {
+        FrameState::Occluded
+      } else {
+        FrameState::Moving
+      };
+    }
+    self.reference = self.previous.clone();
+    return FrameState::Stable;
+  }
+}
+
+#[test]
+fn should_gate_frames_until_settled() {
+  use crate::checkerboard::{Checkerboard, WOOD};
+
+  let corners = [(0.0, 0.0), (320.0, 0.0), (320.0, 320.0), (0.0, 320.0)];
+  let board = Checkerboard::through(corners).with_colors(WOOD);
+  let render = |pieces: &[&str], hand: bool| {
+    let mut frame = board.rgb_with_pieces(320, 320, |square, (u, v)| {
+      let on_piece = u.abs() < 0.25 && v.abs() < 0.25;
+      if on_piece && pieces.contains(&square.to_string().as_str()) {
+        return Some(Rgb([20, 20, 20]));
+      }
+      return None;
+    });
+    if hand {
+      for y in 61..200 {
+        for x in 101..320 {
+          frame.put_pixel(x, y, Rgb([224, 172, 140]));
+        }
+      }
+    }
+    frame
+  };
+
+  let mut detector = OcclusionDetector::new().with_settle_frames(2);
+  let before = render(&["e2"], false);
+  assert_eq!(detector.update(&before), FrameState::Moving);
+  assert_eq!(detector.update(&before), FrameState::Moving);
+  assert_eq!(detector.update(&before), FrameState::Stable);
+
+  let hand = render(&["e2"], true);
+  assert_eq!(detector.update(&hand), FrameState::Occluded);
+  assert_eq!(detector.update(&hand), FrameState::Occluded);
+
+  let after = render(&["e4"], false);
+  assert_eq!(detector.update(&after), FrameState::Moving);
+  assert_eq!(detector.update(&after), FrameState::Moving);
+  assert_eq!(detector.update(&after), FrameState::Stable);
+}
+
+#[test]
+fn should_settle_on_large_changes_without_a_hand() {
+  use crate::checkerboard::{Checkerboard, GREEN};
+
+  // pieces that fill most of their squares, so castling changes one region four squares long.
+  // The green board is never mistaken for skin, like wood can be
+  let corners = [(0.0, 0.0), (320.0, 0.0), (320.0, 320.0), (0.0, 320.0)];
+  let board = Checkerboard::through(corners).with_colors(GREEN);
+  let render = |pieces: &[&str]| {
+    board.rgb_with_pieces(320, 320, |square, (u, v)| {
+      let on_piece = u.abs() < 0.45 && v.abs() < 0.45;
+      if on_piece && pieces.contains(&square.to_string().as_str()) {
+        return Some(Rgb([20, 20, 20]));
+      }
+      return None;
+    })
+  };
+
+  let mut detector = OcclusionDetector::new().with_settle_frames(2);
+  let before = render(&["e1", "h1"]);
+  detector.update(&before);
+  detector.update(&before);
+  assert_eq!(detector.update(&before), FrameState::Stable);
+
+  let castled = render(&["g1", "f1"]);
+  assert_eq!(detector.update(&castled), FrameState::Occluded);
+  assert_eq!(detector.update(&castled), FrameState::Occluded);
+  assert_eq!(detector.update(&castled), FrameState::Stable);
+  assert_eq!(detector.update(&castled), FrameState::Stable);
+}
@@ -0,0 +1,201 @@
+use crate::segmentation::BoardSegmentation;
+use crate::square::Square;
+use image::{GrayImage, Rgb, RgbImage};
+use imageproc::edges::canny;
+
+// fraction of the square trimmed from each side before measuring, hides the grid lines
+pub(crate) const SQUARE_MARGIN: f32 = 0.15;
+
+// feature values at which a square is considered fully occupied
+const EDGE_DENSITY_SATURATION: f32 = 0.06;
+const DEVIATION_SATURATION: f32 = 30.0;
+const FOREGROUND_SATURATION: f32 = 0.2;
+
+// colour distance from the square background for a pixel to count as foreground
+const FOREGROUND_DISTANCE: f32 = 45.0;
+
+/// Which squares of a board hold a piece.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occupancy {
+  /// Bit `Square::index` is set when that square is occupied.
+  pub bitboard: u64,
+
+  /// Confidence in the classification of each square from 0.0 to 1.0, indexed by `Square::index`.
+  pub confidence: [f32; 64],
+}
+
+impl Occupancy {
+  pub fn is_occupied(&self, square: Square) -> bool {
+    return self.bitboard & (1 << square.index()) != 0;
+  }
+
+  pub fn confidence(&self, square: Square) -> f32 {
+    return self.confidence[square.index()];
+  }
+
+  /// Occupied squares from a1 to h8.
+  pub fn occupied(&self) -> Vec<Square> {
+    return Square::all().filter(|s| self.is_occupied(*s)).collect();
+  }
+}
+
+pub(crate) fn color_distance(a: &Rgb<u8>, b: &[f32; 3]) -> f32 {
+  let mut sum = 0.0;
+  for c in 0..3 {
+    sum += (a[c] as f32 - b[c]).powf(2.0);
+  }
+  return sum.sqrt();
+}
+
+fn median(values: &mut [f32]) -> f32 {
+  if values.is_empty() {
+    return 0.0;
+  }
+  values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  return values[values.len() / 2];
+}
+
+// median colour of the thin ring just inside the margin of every square of one colour. pieces
+// rarely reach the edge of their square so this approximates the empty square colour
+pub(crate) fn background_color(segmentation: &BoardSegmentation, light: bool) -> [f32; 3] {
+  let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+  for square in Square::all().filter(|s| s.is_light() == light) {
+    let (x, y, width, height) = segmentation.square_bounds(square, SQUARE_MARGIN / 2.0);
+    let ring = (width.min(height) / 10).max(1);
+    for py in y..y + height {
+      for px in x..x + width {
+        let on_ring =
+          px < x + ring || px >= x + width - ring || py < y + ring || py >= y + height - ring;
+        if on_ring {
+          let p = segmentation.board.get_pixel(px, py);
+          for c in 0..3 {
+            channels[c].push(p[c] as f32);
+          }
+        }
+      }
+    }
+  }
+  return [
+    median(&mut channels[0]),
+    median(&mut channels[1]),
+    median(&mut channels[2]),
+  ];
+}
+
+/// Statistics a square is classified on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SquareFeatures {
+  /// Fraction of pixels on a Canny edge.
+  pub edge_density: f32,
+  /// Standard deviation of luminance.
+  pub deviation: f32,
+  /// Fraction of pixels that differ from the empty square colour.
+  pub foreground: f32,
+}
+
+impl SquareFeatures {
+  /// Combined evidence of a piece from 0.0 (empty) to 1.0 (occupied).
+  pub fn score(&self) -> f32 {
+    let edge = (self.edge_density / EDGE_DENSITY_SATURATION).min(1.0);
+    let deviation = (self.deviation / DEVIATION_SATURATION).min(1.0);
+    let foreground = (self.foreground / FOREGROUND_SATURATION).min(1.0);
+    return (edge + deviation + foreground) / 3.0;
+  }
+}
+
+fn square_features(
+  board: &RgbImage,
+  gray: &GrayImage,
+  edges: &GrayImage,
+  (x, y, width, height): (u32, u32, u32, u32),
+  background: &[f32; 3],
+) -> SquareFeatures {
+  let mut edge_count = 0;
+  let mut foreground_count = 0;
+  let mut sum = 0.0;
+  let mut sum_squared = 0.0;
+  for py in y..y + height {
+    for px in x..x + width {
+      if edges.get_pixel(px, py)[0] > 0 {
+        edge_count += 1;
+      }
+      if color_distance(board.get_pixel(px, py), background) > FOREGROUND_DISTANCE {
+        foreground_count += 1;
+      }
+      let v = gray.get_pixel(px, py)[0] as f32;
+      sum += v;
+      sum_squared += v * v;
+    }
+  }
+
+  let count = (width * height) as f32;
+  let mean = sum / count;
+  return SquareFeatures {
+    edge_density: edge_count as f32 / count,
+    deviation: (sum_squared / count - mean * mean).max(0.0).sqrt(),
+    foreground: foreground_count as f32 / count,
+  };
+}
+
+/// Measures the occupancy features of every square, indexed by `Square::index`.
+pub fn square_features_all(segmentation: &BoardSegmentation) -> Vec<SquareFeatures> {
+  let board = &segmentation.board;
+  let gray = image::DynamicImage::ImageRgb8(board.clone()).into_luma8();
+  let edges = canny(&gray, 50.0, 80.0);
+  let light = background_color(segmentation, true);
+  let dark = background_color(segmentation, false);
+
+  return Square::all()
+    .map(|square| {
+      let background = if square.is_light() { &light } else { &dark };
+      let bounds = segmentation.square_bounds(square, SQUARE_MARGIN);
+      square_features(board, &gray, &edges, bounds, background)
+    })
+    .collect();
+}
+
+/// Classifies every square of a segmented board as empty or occupied.
+pub fn occupancy(segmentation: &BoardSegmentation) -> Occupancy {
+  let mut bitboard = 0;
+  let mut confidence = [0.0; 64];
+  for (index, features) in square_features_all(segmentation).iter().enumerate() {
+    let score = features.score();
+    if score >= 0.5 {
+      bitboard |= 1 << index;
+    }
+    confidence[index] = ((score - 0.5).abs() * 2.0).min(1.0);
+  }
+
+  return Occupancy {
+    bitboard,
+    confidence,
+  };
+}
+
+#[test]
+fn should_find_occupied_squares() {
+  use crate::checkerboard::{Checkerboard, WOOD};
+  use crate::orientation::Rotation;
+  use imageproc::geometric_transformations::Projection;
+
+  let occupied = ["e4", "a1", "h8", "d5"];
+  let corners = [(0.0, 0.0), (320.0, 0.0), (320.0, 320.0), (0.0, 320.0)];
+  let checkerboard = Checkerboard::through(corners).with_colors(WOOD);
+  // round pieces 20 pixels across
+  let board = checkerboard.rgb_with_pieces(320, 320, |square, (u, v)| {
+    if !occupied.contains(&square.to_string().as_str()) || u * u + v * v >= 0.0625 {
+      return None;
+    }
+    if square.rank() < 4 {
+      return Some(Rgb([250, 250, 250]));
+    }
+    return Some(Rgb([20, 20, 20]));
+  });
+  let mut segmentation = BoardSegmentation::new(corners, Projection::scale(1.0, 1.0), board);
+  segmentation.rotation = Rotation::Rotate0;
+
+  let result = occupancy(&segmentation);
+  let names: Vec<String> = result.occupied().iter().map(|s| s.to_string()).collect();
+  assert_eq!(names, vec!["a1", "e4", "d5", "h8"]);
+  assert!(result.confidence.iter().all(|c| *c > 0.0));
+}
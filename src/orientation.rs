@@ -0,0 +1,201 @@
+use crate::square::{Square, BOARD_WIDTH};
+use image::{GrayImage, RgbImage};
+
+// minimum difference in mean luminance between the two home bands to trust piece placement
+const PIECE_SIDE_THRESHOLD: f32 = 4.0;
+
+/// Clockwise rotation of the rectified board relative to the standard diagram view, where a1 is
+/// in the bottom-left corner and the white pieces start at the bottom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+  /// a1 is in the bottom-left corner.
+  Rotate0,
+  /// a1 is in the top-left corner.
+  Rotate90,
+  /// a1 is in the top-right corner.
+  Rotate180,
+  /// a1 is in the bottom-right corner.
+  Rotate270,
+}
+
+impl Rotation {
+  /// Row and column of `square` within the rectified board.
+  pub fn cell(&self, square: Square) -> (u32, u32) {
+    let last = (BOARD_WIDTH - 1) as u32;
+    let file = square.file() as u32;
+    let rank = square.rank() as u32;
+    return match self {
+      Rotation::Rotate0 => (last - rank, file),
+      Rotation::Rotate90 => (file, rank),
+      Rotation::Rotate180 => (rank, last - file),
+      Rotation::Rotate270 => (last - file, last - rank),
+    };
+  }
+
+  /// Square at `row` and `column` of the rectified board.
+  pub fn square(&self, row: u32, column: u32) -> Option<Square> {
+    let last = (BOARD_WIDTH - 1) as u32;
+    if row > last || column > last {
+      return None;
+    }
+    let (file, rank) = match self {
+      Rotation::Rotate0 => (column, last - row),
+      Rotation::Rotate90 => (row, column),
+      Rotation::Rotate180 => (last - column, row),
+      Rotation::Rotate270 => (last - row, last - column),
+    };
+    return Square::new(file as u8, rank as u8);
+  }
+}
+
+// mean luminance of the central half of every cell, indexed [row][column]
+#[allow(clippy::needless_range_loop)]
+fn cell_means(gray: &GrayImage) -> [[f32; 8]; 8] {
+  let (width, height) = gray.dimensions();
+  let cell_width = width as f32 / BOARD_WIDTH as f32;
+  let cell_height = height as f32 / BOARD_WIDTH as f32;
+  let mut means = [[0.0; 8]; 8];
+  for row in 0..8 {
+    for column in 0..8 {
+      let x0 = ((column as f32 + 0.25) * cell_width) as u32;
+      let x1 = (((column as f32 + 0.75) * cell_width) as u32).max(x0 + 1).min(width);
+      let y0 = ((row as f32 + 0.25) * cell_height) as u32;
+      let y1 = (((row as f32 + 0.75) * cell_height) as u32).max(y0 + 1).min(height);
+      let mut sum = 0.0;
+      let mut count = 0;
+      for y in y0..y1 {
+        for x in x0..x1 {
+          sum += gray.get_pixel(x, y)[0] as f32;
+          count += 1;
+        }
+      }
+      if count > 0 {
+        means[row][column] = sum / count as f32;
+      }
+    }
+  }
+  return means;
+}
+
+// positive when cells with an even row + column are lighter, as in the standard view
+fn parity_score(means: &[[f32; 8]; 8]) -> f32 {
+  let mut even = 0.0;
+  let mut odd = 0.0;
+  for (row, cells) in means.iter().enumerate() {
+    for (column, mean) in cells.iter().enumerate() {
+      if (row + column) % 2 == 0 {
+        even += mean;
+      } else {
+        odd += mean;
+      }
+    }
+  }
+  return (even - odd) / 32.0;
+}
+
+// mean luminance of the two rows (or columns) along each edge of the board
+#[allow(clippy::needless_range_loop)]
+fn band_means(means: &[[f32; 8]; 8], rows: bool) -> (f32, f32) {
+  let mut start = 0.0;
+  let mut end = 0.0;
+  for a in 0..2 {
+    for b in 0..8 {
+      if rows {
+        start += means[a][b];
+        end += means[7 - a][b];
+      } else {
+        start += means[b][a];
+        end += means[b][7 - a];
+      }
+    }
+  }
+  return (start / 16.0, end / 16.0);
+}
+
+/// Decides which corner of the rectified board is a1.
+///
+/// Square parity (h1 is light) narrows the choice to two opposite corners. Between those the
+/// side whose two outer ranks are brighter is assumed to hold the white pieces; when no pieces
+/// stand out the side closest to the standard view is chosen.
+pub fn detect_rotation(board: &RgbImage) -> Rotation {
+  let gray = image::DynamicImage::ImageRgb8(board.clone()).into_luma8();
+  let means = cell_means(&gray);
+
+  if parity_score(&means) >= 0.0 {
+    let (top, bottom) = band_means(&means, true);
+    if top - bottom > PIECE_SIDE_THRESHOLD {
+      return Rotation::Rotate180;
+    }
+    return Rotation::Rotate0;
+  }
+
+  let (left, right) = band_means(&means, false);
+  if right - left > PIECE_SIDE_THRESHOLD {
+    return Rotation::Rotate270;
+  }
+  return Rotation::Rotate90;
+}
+
+#[cfg(test)]
+fn render_board(rotation: Rotation, pieces: bool) -> RgbImage {
+  use crate::checkerboard::{Checkerboard, WOOD};
+
+  // each quarter turn moves the corner of the board at a8 one corner on, anticlockwise
+  let mut corners = [(0.0, 0.0), (320.0, 0.0), (320.0, 320.0), (0.0, 320.0)];
+  let turns = match rotation {
+    Rotation::Rotate0 => 0,
+    Rotation::Rotate90 => 1,
+    Rotation::Rotate180 => 2,
+    Rotation::Rotate270 => 3,
+  };
+  corners.rotate_left(turns);
+  let board = Checkerboard::through(corners).with_colors(WOOD);
+  return board.rgb_with_pieces(320, 320, |square, (u, v)| {
+    let on_piece = pieces && u.abs() < 0.25 && v.abs() < 0.25;
+    if on_piece && square.rank() < 2 {
+      return Some(image::Rgb([250, 250, 250]));
+    }
+    if on_piece && square.rank() > 5 {
+      return Some(image::Rgb([10, 10, 10]));
+    }
+    return None;
+  });
+}
+
+#[test]
+fn should_round_trip_cells() {
+  for rotation in [
+    Rotation::Rotate0,
+    Rotation::Rotate90,
+    Rotation::Rotate180,
+    Rotation::Rotate270,
+  ]
+  .iter()
+  {
+    for square in Square::all() {
+      let (row, column) = rotation.cell(square);
+      assert_eq!(rotation.square(row, column), Some(square));
+    }
+  }
+  assert_eq!(Rotation::Rotate90.cell("a1".parse().unwrap()), (0, 0));
+  assert_eq!(Rotation::Rotate270.cell("a1".parse().unwrap()), (7, 7));
+}
+
+#[test]
+fn should_detect_rotation() {
+  for rotation in [
+    Rotation::Rotate0,
+    Rotation::Rotate90,
+    Rotation::Rotate180,
+    Rotation::Rotate270,
+  ]
+  .iter()
+  {
+    assert_eq!(detect_rotation(&render_board(*rotation, true)), *rotation);
+  }
+  // without pieces only parity is known
+  assert_eq!(
+    detect_rotation(&render_board(Rotation::Rotate180, false)),
+    Rotation::Rotate0
+  );
+}
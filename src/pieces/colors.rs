@@ -0,0 +1,207 @@
+use crate::occupancy::{background_color, color_distance, Occupancy};
+use crate::segmentation::BoardSegmentation;
+use crate::square::Square;
+use image::imageops::crop_imm;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use imageproc::distance_transform::Norm;
+use imageproc::morphology::erode;
+
+// colour distance from the square background for a pixel to be considered part of a piece
+const PIECE_DISTANCE: f32 = 20.0;
+
+// smaller than the occupancy margin so piece outlines stay closed within the crop
+pub(super) const COLOR_MARGIN: f32 = 0.04;
+
+// piece outlines are assumed to be at most this fraction of the square size thick
+const OUTLINE_FRACTION: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PieceColor {
+  White,
+  Black,
+}
+
+impl PieceColor {
+  pub fn opposite(&self) -> PieceColor {
+    return match self {
+      PieceColor::White => PieceColor::Black,
+      PieceColor::Black => PieceColor::White,
+    };
+  }
+}
+
+/// Colour of the piece on every square of a board.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PieceColors {
+  /// `None` for empty squares, indexed by `Square::index`.
+  pub colors: [Option<PieceColor>; 64],
+
+  /// Confidence in each colour from 0.0 to 1.0, indexed by `Square::index`.
+  pub confidence: [f32; 64],
+}
+
+impl PieceColors {
+  pub fn color(&self, square: Square) -> Option<PieceColor> {
+    return self.colors[square.index()];
+  }
+
+  pub fn confidence(&self, square: Square) -> f32 {
+    return self.confidence[square.index()];
+  }
+}
+
+pub(super) fn luminance(p: &Rgb<u8>) -> f32 {
+  return 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+}
+
+// fills regions of the mask that cannot be reached from the crop border without crossing the
+// mask. white pieces are often filled with the light square colour and only their outline
+// differs from the background
+fn fill_holes(mask: &GrayImage) -> GrayImage {
+  let (width, height) = mask.dimensions();
+  let mut filled = GrayImage::from_pixel(width, height, Luma([255]));
+  let mut stack = Vec::new();
+  for x in 0..width {
+    stack.push((x, 0));
+    stack.push((x, height - 1));
+  }
+  for y in 0..height {
+    stack.push((0, y));
+    stack.push((width - 1, y));
+  }
+
+  while let Some((x, y)) = stack.pop() {
+    if mask.get_pixel(x, y)[0] > 0 || filled.get_pixel(x, y)[0] == 0 {
+      continue;
+    }
+    filled.put_pixel(x, y, Luma([0]));
+    if x > 0 {
+      stack.push((x - 1, y));
+    }
+    if x + 1 < width {
+      stack.push((x + 1, y));
+    }
+    if y > 0 {
+      stack.push((x, y - 1));
+    }
+    if y + 1 < height {
+      stack.push((x, y + 1));
+    }
+  }
+  return filled;
+}
+
+/// Pixels of `crop` that belong to the piece, including any enclosed by its outline.
+pub(super) fn piece_mask(crop: &RgbImage, background: &[f32; 3]) -> GrayImage {
+  let (width, height) = crop.dimensions();
+  let mask = GrayImage::from_fn(width, height, |px, py| {
+    if color_distance(crop.get_pixel(px, py), background) > PIECE_DISTANCE {
+      Luma([255])
+    } else {
+      Luma([0])
+    }
+  });
+  return fill_holes(&mask);
+}
+
+// share of piece pixels that vote white. the filled piece mask is eroded first so the outline
+// drawn around most piece sets, which sits on the mask boundary, does not outvote the body
+fn white_vote(
+  segmentation: &BoardSegmentation,
+  square: Square,
+  background: &[f32; 3],
+  threshold: f32,
+) -> Option<f32> {
+  let (x, y, width, height) = segmentation.square_bounds(square, COLOR_MARGIN);
+  let crop = crop_imm(&segmentation.board, x, y, width, height).to_image();
+  let filled = piece_mask(&crop, background);
+  let radius = (width.min(height) / OUTLINE_FRACTION).max(1) as u8;
+  let eroded = erode(&filled, Norm::LInf, radius);
+
+  let mut votes = (0.0, 0.0);
+  let mut fallback_votes = (0.0, 0.0);
+  for (px, py, p) in crop.enumerate_pixels() {
+    if filled.get_pixel(px, py)[0] == 0 {
+      continue;
+    }
+    let white = if luminance(p) > threshold { 1.0 } else { 0.0 };
+    fallback_votes = (fallback_votes.0 + white, fallback_votes.1 + 1.0);
+    if eroded.get_pixel(px, py)[0] > 0 {
+      votes = (votes.0 + white, votes.1 + 1.0);
+    }
+  }
+
+  // very thin pieces may erode away entirely
+  if votes.1 == 0.0 {
+    votes = fallback_votes;
+  }
+  if votes.1 == 0.0 {
+    return None;
+  }
+  return Some(votes.0 / votes.1);
+}
+
+/// Classifies the colour of the piece on every occupied square.
+///
+/// Pixels that differ from the square's background colour are taken to be the piece and vote
+/// white when they are brighter than the midpoint between the light and dark square colours.
+pub fn piece_colors(segmentation: &BoardSegmentation, occupancy: &Occupancy) -> PieceColors {
+  let light = background_color(segmentation, true);
+  let dark = background_color(segmentation, false);
+  let threshold = (luminance(&Rgb([light[0] as u8, light[1] as u8, light[2] as u8]))
+    + luminance(&Rgb([dark[0] as u8, dark[1] as u8, dark[2] as u8])))
+    / 2.0;
+
+  let mut colors = [None; 64];
+  let mut confidence = [0.0; 64];
+  for square in occupancy.occupied() {
+    let background = if square.is_light() { &light } else { &dark };
+    if let Some(vote) = white_vote(segmentation, square, background, threshold) {
+      let index = square.index();
+      colors[index] = Some(if vote >= 0.5 {
+        PieceColor::White
+      } else {
+        PieceColor::Black
+      });
+      confidence[index] = (vote - 0.5).abs() * 2.0 * occupancy.confidence[index];
+    }
+  }
+
+  return PieceColors { colors, confidence };
+}
+
+#[test]
+fn should_classify_piece_colors() {
+  use crate::checkerboard::{Checkerboard, GREEN};
+  use crate::occupancy::occupancy;
+  use crate::orientation::Rotation;
+  use imageproc::geometric_transformations::Projection;
+
+  let corners = [(0.0, 0.0), (320.0, 0.0), (320.0, 320.0), (0.0, 320.0)];
+  let checkerboard = Checkerboard::through(corners).with_colors(GREEN);
+  // round pieces 20 pixels across with a gray outline
+  let board = checkerboard.rgb_with_pieces(320, 320, |square, (u, v)| {
+    let (on_piece, on_outline) = (u * u + v * v < 0.0625, u * u + v * v < 0.09);
+    let name = square.to_string();
+    if ["c3", "d3"].contains(&name.as_str()) && on_piece {
+      return Some(Rgb([245, 245, 245]));
+    }
+    if ["e6", "f6"].contains(&name.as_str()) && on_piece {
+      return Some(Rgb([40, 40, 40]));
+    }
+    if ["c3", "d3", "e6", "f6"].contains(&name.as_str()) && on_outline {
+      return Some(Rgb([90, 90, 90]));
+    }
+    return None;
+  });
+  let mut segmentation = BoardSegmentation::new(corners, Projection::scale(1.0, 1.0), board);
+  segmentation.rotation = Rotation::Rotate0;
+
+  let colors = piece_colors(&segmentation, &occupancy(&segmentation));
+  let color = |name: &str| colors.color(name.parse().unwrap());
+  assert_eq!(color("c3"), Some(PieceColor::White));
+  assert_eq!(color("d3"), Some(PieceColor::White));
+  assert_eq!(color("e6"), Some(PieceColor::Black));
+  assert_eq!(color("f6"), Some(PieceColor::Black));
+  assert_eq!(color("e4"), None);
+}
@@ -0,0 +1,7 @@
+mod colors;
+mod piece;
+mod templates;
+
+pub use colors::{piece_colors, PieceColor, PieceColors};
+pub use piece::{Piece, PieceKind};
+pub use templates::{starting_position, PieceMatch, PieceTemplates, TEMPLATE_SIZE};
@@ -0,0 +1,79 @@
+use super::PieceColor;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PieceKind {
+  Pawn,
+  Knight,
+  Bishop,
+  Rook,
+  Queen,
+  King,
+}
+
+impl PieceKind {
+  pub const ALL: [PieceKind; 6] = [
+    PieceKind::Pawn,
+    PieceKind::Knight,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+    PieceKind::Queen,
+    PieceKind::King,
+  ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Piece {
+  pub color: PieceColor,
+  pub kind: PieceKind,
+}
+
+impl Piece {
+  pub fn new(color: PieceColor, kind: PieceKind) -> Self {
+    Piece { color, kind }
+  }
+
+  /// All 12 distinct pieces, white first.
+  pub fn all() -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    for color in [PieceColor::White, PieceColor::Black].iter() {
+      for kind in PieceKind::ALL.iter() {
+        pieces.push(Piece::new(*color, *kind));
+      }
+    }
+    return pieces;
+  }
+
+  /// FEN letter of the piece, upper case for white.
+  pub fn to_char(&self) -> char {
+    let c = match self.kind {
+      PieceKind::Pawn => 'p',
+      PieceKind::Knight => 'n',
+      PieceKind::Bishop => 'b',
+      PieceKind::Rook => 'r',
+      PieceKind::Queen => 'q',
+      PieceKind::King => 'k',
+    };
+    return match self.color {
+      PieceColor::White => c.to_ascii_uppercase(),
+      PieceColor::Black => c,
+    };
+  }
+
+  pub fn from_char(c: char) -> Option<Piece> {
+    let kind = match c.to_ascii_lowercase() {
+      'p' => PieceKind::Pawn,
+      'n' => PieceKind::Knight,
+      'b' => PieceKind::Bishop,
+      'r' => PieceKind::Rook,
+      'q' => PieceKind::Queen,
+      'k' => PieceKind::King,
+      _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() {
+      PieceColor::White
+    } else {
+      PieceColor::Black
+    };
+    return Some(Piece::new(color, kind));
+  }
+}
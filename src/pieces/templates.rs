@@ -0,0 +1,288 @@
+// Piece recognition for digitally rendered boards, where every glyph of a piece set is drawn
+// identically and a single labelled board is enough to learn it.
+
+use super::colors::{luminance, piece_mask, COLOR_MARGIN};
+use super::{Piece, PieceColor, PieceKind};
+use crate::occupancy::{background_color, Occupancy};
+use crate::segmentation::BoardSegmentation;
+use crate::square::Square;
+use image::imageops::{crop_imm, resize, FilterType};
+use image::{GrayImage, Luma, Rgb};
+
+/// Side length in pixels that templates and square crops are scaled to before matching.
+pub const TEMPLATE_SIZE: u32 = 32;
+
+// templates are matched at every offset up to this many pixels to absorb small misalignment
+const SEARCH_RADIUS: u32 = 2;
+
+/// The best matching template for a square.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PieceMatch {
+  pub piece: Piece,
+
+  /// Zero-mean normalised cross-correlation from -1.0 to 1.0.
+  pub score: f32,
+}
+
+/// Glyphs of a piece set learned from a labelled board.
+#[derive(Clone, Debug)]
+pub struct PieceTemplates {
+  templates: Vec<(Piece, GrayImage)>,
+}
+
+const BACK_RANK: [PieceKind; 8] = [
+  PieceKind::Rook,
+  PieceKind::Knight,
+  PieceKind::Bishop,
+  PieceKind::Queen,
+  PieceKind::King,
+  PieceKind::Bishop,
+  PieceKind::Knight,
+  PieceKind::Rook,
+];
+
+/// Pieces of the standard starting position.
+pub fn starting_position() -> Vec<(Square, Piece)> {
+  let mut labels = Vec::new();
+  for file in 0..8 {
+    let kind = BACK_RANK[file as usize];
+    let pieces = [
+      (0, Piece::new(PieceColor::White, kind)),
+      (1, Piece::new(PieceColor::White, PieceKind::Pawn)),
+      (6, Piece::new(PieceColor::Black, PieceKind::Pawn)),
+      (7, Piece::new(PieceColor::Black, kind)),
+    ];
+    for (rank, piece) in pieces.iter() {
+      if let Some(square) = Square::new(file, *rank) {
+        labels.push((square, *piece));
+      }
+    }
+  }
+  labels.sort_by_key(|(square, _)| *square);
+  return labels;
+}
+
+// grayscale crop of a square where everything outside the piece is replaced by a neutral value
+// halfway between the two square colours, so a glyph looks the same on light and dark squares
+fn glyph(
+  segmentation: &BoardSegmentation,
+  square: Square,
+  backgrounds: &([f32; 3], [f32; 3]),
+) -> GrayImage {
+  let (light, dark) = backgrounds;
+  let background = if square.is_light() { light } else { dark };
+  let to_rgb = |c: &[f32; 3]| Rgb([c[0] as u8, c[1] as u8, c[2] as u8]);
+  let neutral = ((luminance(&to_rgb(light)) + luminance(&to_rgb(dark))) / 2.0) as u8;
+
+  let (x, y, width, height) = segmentation.square_bounds(square, COLOR_MARGIN);
+  let crop = crop_imm(&segmentation.board, x, y, width, height).to_image();
+  let mask = piece_mask(&crop, background);
+  let glyph = GrayImage::from_fn(width, height, |px, py| {
+    if mask.get_pixel(px, py)[0] > 0 {
+      Luma([luminance(crop.get_pixel(px, py)) as u8])
+    } else {
+      Luma([neutral])
+    }
+  });
+
+  let size = TEMPLATE_SIZE + 2 * SEARCH_RADIUS;
+  return resize(&glyph, size, size, FilterType::Triangle);
+}
+
+fn zncc(image: &GrayImage, template: &GrayImage, offset_x: u32, offset_y: u32) -> f32 {
+  let (width, height) = template.dimensions();
+  let count = (width * height) as f32;
+  let mut image_mean = 0.0;
+  let mut template_mean = 0.0;
+  for y in 0..height {
+    for x in 0..width {
+      image_mean += image.get_pixel(x + offset_x, y + offset_y)[0] as f32;
+      template_mean += template.get_pixel(x, y)[0] as f32;
+    }
+  }
+  image_mean /= count;
+  template_mean /= count;
+
+  let mut covariance = 0.0;
+  let mut image_variance = 0.0;
+  let mut template_variance = 0.0;
+  for y in 0..height {
+    for x in 0..width {
+      let a = image.get_pixel(x + offset_x, y + offset_y)[0] as f32 - image_mean;
+      let b = template.get_pixel(x, y)[0] as f32 - template_mean;
+      covariance += a * b;
+      image_variance += a * a;
+      template_variance += b * b;
+    }
+  }
+
+  let denominator = (image_variance * template_variance).sqrt();
+  if denominator == 0.0 {
+    return 0.0;
+  }
+  return covariance / denominator;
+}
+
+impl PieceTemplates {
+  /// Learns templates from the labelled squares of `reference`. The crops of every piece are
+  /// averaged into a single template, so the result holds one template per distinct piece.
+  pub fn learn(reference: &BoardSegmentation, labels: &[(Square, Piece)]) -> PieceTemplates {
+    let backgrounds = (
+      background_color(reference, true),
+      background_color(reference, false),
+    );
+    let area = (TEMPLATE_SIZE * TEMPLATE_SIZE) as usize;
+    let mut sums: Vec<(Piece, Vec<f32>, usize)> = Vec::new();
+    for (square, piece) in labels.iter() {
+      let glyph = glyph(reference, *square, &backgrounds);
+      let crop = crop_imm(
+        &glyph,
+        SEARCH_RADIUS,
+        SEARCH_RADIUS,
+        TEMPLATE_SIZE,
+        TEMPLATE_SIZE,
+      )
+      .to_image();
+      let index = match sums.iter().position(|(p, _, _)| p == piece) {
+        Some(index) => index,
+        None => {
+          sums.push((*piece, vec![0.0; area], 0));
+          sums.len() - 1
+        }
+      };
+      let (_, sum, count) = &mut sums[index];
+      for (total, pixel) in sum.iter_mut().zip(crop.pixels()) {
+        *total += pixel[0] as f32;
+      }
+      *count += 1;
+    }
+
+    let templates = sums
+      .into_iter()
+      .map(|(piece, sum, count)| {
+        let template = GrayImage::from_fn(TEMPLATE_SIZE, TEMPLATE_SIZE, |x, y| {
+          let total = sum[(y * TEMPLATE_SIZE + x) as usize];
+          Luma([(total / count as f32).round() as u8])
+        });
+        (piece, template)
+      })
+      .collect();
+    return PieceTemplates { templates };
+  }
+
+  /// Learns templates from a board set up in the standard starting position.
+  pub fn learn_starting_position(reference: &BoardSegmentation) -> PieceTemplates {
+    return PieceTemplates::learn(reference, &starting_position());
+  }
+
+  pub fn len(&self) -> usize {
+    return self.templates.len();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.templates.is_empty();
+  }
+
+  fn best_match(&self, glyph: &GrayImage) -> Option<PieceMatch> {
+    let mut best: Option<PieceMatch> = None;
+    for (piece, template) in self.templates.iter() {
+      for offset_y in 0..=2 * SEARCH_RADIUS {
+        for offset_x in 0..=2 * SEARCH_RADIUS {
+          let score = zncc(glyph, template, offset_x, offset_y);
+          if best.is_none_or(|b| score > b.score) {
+            best = Some(PieceMatch {
+              piece: *piece,
+              score,
+            });
+          }
+        }
+      }
+    }
+    return best;
+  }
+
+  /// Matches a single square of `segmentation` against every template.
+  pub fn classify(&self, segmentation: &BoardSegmentation, square: Square) -> Option<PieceMatch> {
+    let backgrounds = (
+      background_color(segmentation, true),
+      background_color(segmentation, false),
+    );
+    return self.best_match(&glyph(segmentation, square, &backgrounds));
+  }
+
+  /// Matches every occupied square, indexed by `Square::index`.
+  pub fn classify_board(
+    &self,
+    segmentation: &BoardSegmentation,
+    occupancy: &Occupancy,
+  ) -> [Option<PieceMatch>; 64] {
+    let backgrounds = (
+      background_color(segmentation, true),
+      background_color(segmentation, false),
+    );
+    let mut matches = [None; 64];
+    for square in occupancy.occupied() {
+      matches[square.index()] = self.best_match(&glyph(segmentation, square, &backgrounds));
+    }
+    return matches;
+  }
+}
+
+#[cfg(test)]
+fn render_position(pieces: &[(Square, Piece)]) -> BoardSegmentation {
+  use crate::checkerboard::{Checkerboard, GREEN};
+  use crate::orientation::Rotation;
+  use imageproc::geometric_transformations::Projection;
+
+  let corners = [(0.0, 0.0), (384.0, 0.0), (384.0, 384.0), (0.0, 384.0)];
+  let checkerboard = Checkerboard::through(corners).with_colors(GREEN);
+  // a plain glyph for every kind of piece, in pixels from the middle of a 48 pixel square
+  let board = checkerboard.rgb_with_pieces(384, 384, |square, (u, v)| {
+    let (cx, cy) = ((u * 48.0).round() as i32, (v * 48.0).round() as i32);
+    let piece = pieces.iter().find(|(s, _)| *s == square).map(|(_, p)| *p)?;
+    let on_glyph = match piece.kind {
+      PieceKind::Pawn => cx * cx + cy * cy < 64,
+      PieceKind::Knight => cx.abs() < 12 && cy.abs() < 12 && cx < cy,
+      PieceKind::Bishop => cx.abs() < 4 && cy.abs() < 14,
+      PieceKind::Rook => cx.abs() < 12 && cy.abs() < 12,
+      PieceKind::Queen => (cx.abs() < 4 || cy.abs() < 4) && cx.abs() < 14 && cy.abs() < 14,
+      PieceKind::King => (cx - cy).abs() < 4 && cx.abs() < 14 || (cx + cy).abs() < 4,
+    };
+    if !on_glyph {
+      return None;
+    }
+    if piece.color == PieceColor::White {
+      return Some(Rgb([250, 250, 250]));
+    }
+    return Some(Rgb([30, 30, 30]));
+  });
+  let mut segmentation = BoardSegmentation::new(corners, Projection::scale(1.0, 1.0), board);
+  segmentation.rotation = Rotation::Rotate0;
+  return segmentation;
+}
+
+#[test]
+fn should_classify_pieces_from_starting_position() {
+  use crate::occupancy::occupancy;
+
+  let templates = PieceTemplates::learn_starting_position(&render_position(&starting_position()));
+  assert_eq!(templates.len(), 12);
+
+  let piece = |color, kind, name: &str| (name.parse::<Square>().unwrap(), Piece::new(color, kind));
+  let position = vec![
+    piece(PieceColor::White, PieceKind::King, "g1"),
+    piece(PieceColor::White, PieceKind::Queen, "d4"),
+    piece(PieceColor::White, PieceKind::Knight, "c3"),
+    piece(PieceColor::Black, PieceKind::Bishop, "b4"),
+    piece(PieceColor::Black, PieceKind::Rook, "e8"),
+    piece(PieceColor::Black, PieceKind::King, "g8"),
+    piece(PieceColor::Black, PieceKind::Pawn, "f5"),
+  ];
+  let segmentation = render_position(&position);
+  let matches = templates.classify_board(&segmentation, &occupancy(&segmentation));
+  for (square, piece) in position.iter() {
+    let found = matches[square.index()].expect("occupied square was not classified");
+    assert_eq!(found.piece, *piece, "on {}", square);
+  }
+  assert_eq!(matches.iter().filter(|m| m.is_some()).count(), position.len());
+}
@@ -0,0 +1,330 @@
+use crate::pieces::{Piece, PieceColor, PieceMatch};
+use crate::square::{Square, BOARD_WIDTH};
+use std::error::Error;
+use std::fmt;
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Reasons a FEN string could not be parsed.
+#[derive(Debug, PartialEq)]
+pub enum FenError {
+  /// The string does not have the six space separated fields.
+  WrongFieldCount(usize),
+
+  /// The piece placement field does not describe an 8x8 board.
+  InvalidPlacement,
+
+  InvalidSideToMove,
+
+  InvalidCastling,
+
+  InvalidEnPassant,
+
+  /// The halfmove clock or fullmove number is not a number.
+  InvalidCounter,
+}
+
+impl fmt::Display for FenError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FenError::WrongFieldCount(count) => write!(f, "expected 6 FEN fields, found {}", count),
+      FenError::InvalidPlacement => write!(f, "invalid piece placement"),
+      FenError::InvalidSideToMove => write!(f, "invalid side to move"),
+      FenError::InvalidCastling => write!(f, "invalid castling availability"),
+      FenError::InvalidEnPassant => write!(f, "invalid en passant square"),
+      FenError::InvalidCounter => write!(f, "invalid move counter"),
+    }
+  }
+}
+
+impl Error for FenError {}
+
+/// Which sides may still castle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CastlingRights {
+  pub white_king_side: bool,
+  pub white_queen_side: bool,
+  pub black_king_side: bool,
+  pub black_queen_side: bool,
+}
+
+impl CastlingRights {
+  pub fn all() -> Self {
+    CastlingRights {
+      white_king_side: true,
+      white_queen_side: true,
+      black_king_side: true,
+      black_queen_side: true,
+    }
+  }
+
+  fn to_fen(self) -> String {
+    let mut fen = String::new();
+    let flags = [
+      (self.white_king_side, 'K'),
+      (self.white_queen_side, 'Q'),
+      (self.black_king_side, 'k'),
+      (self.black_queen_side, 'q'),
+    ];
+    for (allowed, c) in flags.iter() {
+      if *allowed {
+        fen.push(*c);
+      }
+    }
+    if fen.is_empty() {
+      fen.push('-');
+    }
+    return fen;
+  }
+
+  fn from_fen(fen: &str) -> Result<Self, FenError> {
+    let mut rights = CastlingRights::default();
+    if fen == "-" {
+      return Ok(rights);
+    }
+    for c in fen.chars() {
+      let flag = match c {
+        'K' => &mut rights.white_king_side,
+        'Q' => &mut rights.white_queen_side,
+        'k' => &mut rights.black_king_side,
+        'q' => &mut rights.black_queen_side,
+        _ => return Err(FenError::InvalidCastling),
+      };
+      if *flag {
+        return Err(FenError::InvalidCastling);
+      }
+      *flag = true;
+    }
+    return Ok(rights);
+  }
+}
+
+/// A chess position: piece placement plus the state FEN records alongside it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Position {
+  board: [Option<Piece>; 64],
+  pub side_to_move: PieceColor,
+  pub castling: CastlingRights,
+  pub en_passant: Option<Square>,
+  pub halfmove_clock: u32,
+  pub fullmove_number: u32,
+}
+
+impl Default for Position {
+  fn default() -> Self {
+    Position::empty()
+  }
+}
+
+impl Position {
+  /// A board without pieces, white to move.
+  pub fn empty() -> Self {
+    Position {
+      board: [None; 64],
+      side_to_move: PieceColor::White,
+      castling: CastlingRights::default(),
+      en_passant: None,
+      halfmove_clock: 0,
+      fullmove_number: 1,
+    }
+  }
+
+  pub fn starting() -> Self {
+    return Position::from_fen(STARTING_FEN).unwrap();
+  }
+
+  /// Builds the piece placement from per-square classifications such as
+  /// `PieceTemplates::classify_board`. The classifications are already indexed by chess square,
+  /// so the board orientation found during segmentation is accounted for. The remaining FEN
+  /// fields take their defaults and can be set by the caller.
+  pub fn from_matches(matches: &[Option<PieceMatch>; 64]) -> Self {
+    let mut position = Position::empty();
+    for square in Square::all() {
+      position.set_piece(square, matches[square.index()].map(|m| m.piece));
+    }
+    return position;
+  }
+
+  pub fn piece(&self, square: Square) -> Option<Piece> {
+    return self.board[square.index()];
+  }
+
+  pub fn set_piece(&mut self, square: Square, piece: Option<Piece>) {
+    self.board[square.index()] = piece;
+  }
+
+  /// Occupied squares and their pieces from a1 to h8.
+  pub fn pieces(&self) -> Vec<(Square, Piece)> {
+    return Square::all()
+      .filter_map(|square| self.piece(square).map(|piece| (square, piece)))
+      .collect();
+  }
+
+  /// Bit `Square::index` is set for every occupied square, comparable with
+  /// `Occupancy::bitboard`.
+  pub fn occupancy(&self) -> u64 {
+    let mut bitboard = 0;
+    for (square, _) in self.pieces() {
+      bitboard |= 1 << square.index();
+    }
+    return bitboard;
+  }
+
+  /// The first FEN field, describing where the pieces stand.
+  pub fn placement_fen(&self) -> String {
+    let mut fen = String::new();
+    for rank in (0..BOARD_WIDTH).rev() {
+      let mut empty = 0;
+      for file in 0..BOARD_WIDTH {
+        match Square::new(file, rank).and_then(|square| self.piece(square)) {
+          Some(piece) => {
+            if empty > 0 {
+              fen.push_str(&empty.to_string());
+              empty = 0;
+            }
+            fen.push(piece.to_char());
+          }
+          None => empty += 1,
+        }
+      }
+      if empty > 0 {
+        fen.push_str(&empty.to_string());
+      }
+      if rank > 0 {
+        fen.push('/');
+      }
+    }
+    return fen;
+  }
+
+  pub fn to_fen(&self) -> String {
+    let side_to_move = match self.side_to_move {
+      PieceColor::White => "w",
+      PieceColor::Black => "b",
+    };
+    let en_passant = match self.en_passant {
+      Some(square) => square.to_string(),
+      None => "-".to_string(),
+    };
+    return format!(
+      "{} {} {} {} {} {}",
+      self.placement_fen(),
+      side_to_move,
+      self.castling.to_fen(),
+      en_passant,
+      self.halfmove_clock,
+      self.fullmove_number
+    );
+  }
+
+  fn parse_placement(&mut self, placement: &str) -> Result<(), FenError> {
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != BOARD_WIDTH as usize {
+      return Err(FenError::InvalidPlacement);
+    }
+    for (row, description) in ranks.iter().enumerate() {
+      let rank = BOARD_WIDTH - 1 - row as u8;
+      let mut file = 0;
+      let mut after_digit = false;
+      for c in description.chars() {
+        if let Some(skip) = c.to_digit(10) {
+          // consecutive empty squares are always written as a single digit
+          if skip == 0 || after_digit {
+            return Err(FenError::InvalidPlacement);
+          }
+          file += skip as u8;
+          if file > BOARD_WIDTH {
+            return Err(FenError::InvalidPlacement);
+          }
+          after_digit = true;
+          continue;
+        }
+        let piece = Piece::from_char(c).ok_or(FenError::InvalidPlacement)?;
+        let square = Square::new(file, rank).ok_or(FenError::InvalidPlacement)?;
+        self.set_piece(square, Some(piece));
+        file += 1;
+        after_digit = false;
+      }
+      if file != BOARD_WIDTH {
+        return Err(FenError::InvalidPlacement);
+      }
+    }
+    return Ok(());
+  }
+
+  /// Parses a full six field FEN string. A bare piece placement is also accepted, in which case
+  /// the other fields take their defaults.
+  pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() != 6 && fields.len() != 1 {
+      return Err(FenError::WrongFieldCount(fields.len()));
+    }
+
+    let mut position = Position::empty();
+    position.parse_placement(fields[0])?;
+    if fields.len() == 1 {
+      return Ok(position);
+    }
+
+    position.side_to_move = match fields[1] {
+      "w" => PieceColor::White,
+      "b" => PieceColor::Black,
+      _ => return Err(FenError::InvalidSideToMove),
+    };
+    position.castling = CastlingRights::from_fen(fields[2])?;
+    position.en_passant = match fields[3] {
+      "-" => None,
+      square => Some(square.parse().map_err(|_| FenError::InvalidEnPassant)?),
+    };
+    position.halfmove_clock = fields[4].parse().map_err(|_| FenError::InvalidCounter)?;
+    position.fullmove_number = fields[5].parse().map_err(|_| FenError::InvalidCounter)?;
+    return Ok(position);
+  }
+}
+
+#[test]
+fn should_round_trip_fen() {
+  assert_eq!(Position::starting().to_fen(), STARTING_FEN);
+  assert_eq!(Position::starting().pieces().len(), 32);
+
+  let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+  assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+
+  let fen = "8/8/8/3pP3/8/8/8/4K2k w - d6 0 30";
+  let position = Position::from_fen(fen).unwrap();
+  assert_eq!(position.en_passant, Some("d6".parse().unwrap()));
+  assert_eq!(position.castling, CastlingRights::default());
+  assert_eq!(position.to_fen(), fen);
+}
+
+#[test]
+fn should_reject_invalid_fen() {
+  assert_eq!(
+    Position::from_fen("8/8/8/8/8/8/8 w - - 0 1"),
+    Err(FenError::InvalidPlacement)
+  );
+  assert_eq!(
+    Position::from_fen("44/8/8/8/8/8/8/8 w - - 0 1"),
+    Err(FenError::InvalidPlacement)
+  );
+  assert_eq!(
+    Position::from_fen(&format!("{}/8/8/8/8/8/8/8", "8".repeat(32))),
+    Err(FenError::InvalidPlacement)
+  );
+  assert_eq!(
+    Position::from_fen("8/8/8/8/8/8/8/7X w - - 0 1"),
+    Err(FenError::InvalidPlacement)
+  );
+  assert_eq!(
+    Position::from_fen("8/8/8/8/8/8/8/8 x - - 0 1"),
+    Err(FenError::InvalidSideToMove)
+  );
+  assert_eq!(
+    Position::from_fen("8/8/8/8/8/8/8/8 w KK - 0 1"),
+    Err(FenError::InvalidCastling)
+  );
+  assert_eq!(
+    Position::from_fen("8/8/8/8/8/8/8/8 w - - 0"),
+    Err(FenError::WrongFieldCount(5))
+  );
+}
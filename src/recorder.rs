@@ -0,0 +1,423 @@
+// Records a game from a stream of images of the board, one move per settled change.
+
+use crate::error::SegmentError;
+use crate::moves::{infer_move, infer_move_from_occupancy, AmbiguousDiff, Move};
+use crate::occlusion::{FrameState, OcclusionDetector};
+use crate::occupancy::occupancy;
+use crate::pieces::{PieceColor, PieceTemplates};
+use crate::position::{Position, STARTING_FEN};
+use crate::segmentation::BoardSegmentation;
+use image::DynamicImage;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+// consecutive identical observations required before a change is treated as a move
+const DEFAULT_STABLE_FRAMES: usize = 3;
+
+// movetext lines are wrapped below this length, as the PGN export format requires
+const PGN_LINE_WIDTH: usize = 80;
+
+// the seven tag roster every PGN game starts with, in the required order, and the value used
+// until a tag is set; the result defaults to the state of the recorded game instead
+const ROSTER_TAGS: [(&str, &str); 7] = [
+  ("Event", "?"),
+  ("Site", "?"),
+  ("Date", "????.??.??"),
+  ("Round", "?"),
+  ("White", "?"),
+  ("Black", "?"),
+  ("Result", ""),
+];
+
+/// Reasons a frame could not be recorded.
+#[derive(Debug)]
+pub enum RecorderError {
+  /// The board could not be located in the frame.
+  Segment(SegmentError),
+
+  /// The board settled in a state that no single legal move explains.
+  Diff(AmbiguousDiff),
+}
+
+impl fmt::Display for RecorderError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RecorderError::Segment(err) => write!(f, "segmentation failed: {}", err),
+      RecorderError::Diff(err) => write!(f, "move inference failed: {}", err),
+    }
+  }
+}
+
+impl Error for RecorderError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      RecorderError::Segment(err) => Some(err),
+      RecorderError::Diff(err) => Some(err),
+    }
+  }
+}
+
+impl From<SegmentError> for RecorderError {
+  fn from(err: SegmentError) -> Self {
+    RecorderError::Segment(err)
+  }
+}
+
+impl From<AmbiguousDiff> for RecorderError {
+  fn from(err: AmbiguousDiff) -> Self {
+    RecorderError::Diff(err)
+  }
+}
+
+/// A move of the recorded game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedMove {
+  pub mv: Move,
+
+  /// The move in standard algebraic notation.
+  pub san: String,
+
+  /// Time since the start of the recording at which the move was seen, if frames are
+  /// timestamped.
+  pub timestamp: Option<Duration>,
+}
+
+// what a frame shows of the board, depending on whether pieces can be identified
+#[derive(Clone, Debug, PartialEq)]
+enum Observation {
+  Pieces(Position),
+  Occupancy(u64),
+}
+
+/// Turns a stream of frames into a game score.
+///
+/// Every frame is segmented and compared against the current position. A change is only
+/// accepted once the board has looked the same for several frames in a row, so hands and
+/// pieces in flight are ignored.
+#[derive(Clone, Debug)]
+pub struct GameRecorder {
+  start: Position,
+  position: Position,
+  moves: Vec<RecordedMove>,
+  templates: Option<PieceTemplates>,
+  occlusion: Option<OcclusionDetector>,
+  stable_frames: usize,
+  pending: Option<(Observation, usize)>,
+  tags: Vec<(String, String)>,
+}
+
+impl Default for GameRecorder {
+  fn default() -> Self {
+    GameRecorder::new(Position::starting())
+  }
+}
+
+impl GameRecorder {
+  pub fn new(start: Position) -> Self {
+    GameRecorder {
+      position: start.clone(),
+      start,
+      moves: Vec::new(),
+      templates: None,
+      occlusion: None,
+      stable_frames: DEFAULT_STABLE_FRAMES,
+      pending: None,
+      tags: Vec::new(),
+    }
+  }
+
+  /// Identifies pieces with `templates` instead of relying on occupancy alone, which resolves
+  /// captures where several pieces could have been taken.
+  pub fn with_templates(mut self, templates: PieceTemplates) -> Self {
+    self.templates = Some(templates);
+    return self;
+  }
+
+  /// Skips segmented frames that `detector` does not report as stable, so boards with a hand
+  /// over them are never read.
+  pub fn with_occlusion_detector(mut self, detector: OcclusionDetector) -> Self {
+    self.occlusion = Some(detector);
+    return self;
+  }
+
+  /// Number of identical consecutive frames required before a change is recorded.
+  pub fn with_stable_frames(mut self, frames: usize) -> Self {
+    self.stable_frames = frames.max(1);
+    return self;
+  }
+
+  /// Sets a PGN tag pair such as `White` or `Event`, replacing an existing value. Setting
+  /// `Result` overrides the result worked out from the position, for example after a
+  /// resignation.
+  pub fn set_tag(&mut self, name: &str, value: &str) {
+    match self.tags.iter_mut().find(|(n, _)| n == name) {
+      Some(tag) => tag.1 = value.to_string(),
+      None => self.tags.push((name.to_string(), value.to_string())),
+    }
+  }
+
+  pub fn position(&self) -> &Position {
+    return &self.position;
+  }
+
+  pub fn moves(&self) -> &[RecordedMove] {
+    return &self.moves;
+  }
+
+  /// Segments `frame` and records the move it shows, if any.
+  pub fn push_frame(
+    &mut self,
+    frame: &DynamicImage,
+    timestamp: Option<Duration>,
+  ) -> Result<Option<&RecordedMove>, RecorderError> {
+    let segmentation = crate::segment(frame)?;
+    return self.push_segmentation(&segmentation, timestamp);
+  }
+
+  /// Records the move shown by an already segmented board, if any.
+  pub fn push_segmentation(
+    &mut self,
+    segmentation: &BoardSegmentation,
+    timestamp: Option<Duration>,
+  ) -> Result<Option<&RecordedMove>, RecorderError> {
+    if let Some(detector) = &mut self.occlusion {
+      if detector.update(&segmentation.board) != FrameState::Stable {
+        return Ok(None);
+      }
+    }
+    let occupancy = occupancy(segmentation);
+    let observation = match &self.templates {
+      Some(templates) => Observation::Pieces(Position::from_matches(
+        &templates.classify_board(segmentation, &occupancy),
+      )),
+      None => Observation::Occupancy(occupancy.bitboard),
+    };
+    return self.observe(observation, timestamp);
+  }
+
+  /// Records the move leading to `observed`, whose side to move and other state are ignored.
+  pub fn push_position(
+    &mut self,
+    observed: &Position,
+    timestamp: Option<Duration>,
+  ) -> Result<Option<&RecordedMove>, RecorderError> {
+    return self.observe(Observation::Pieces(observed.clone()), timestamp);
+  }
+
+  /// Records the move leading to a board with the occupancy `bitboard`.
+  pub fn push_occupancy(
+    &mut self,
+    bitboard: u64,
+    timestamp: Option<Duration>,
+  ) -> Result<Option<&RecordedMove>, RecorderError> {
+    return self.observe(Observation::Occupancy(bitboard), timestamp);
+  }
+
+  fn observe(
+    &mut self,
+    observation: Observation,
+    timestamp: Option<Duration>,
+  ) -> Result<Option<&RecordedMove>, RecorderError> {
+    let count = match &self.pending {
+      Some((pending, count)) if *pending == observation => count + 1,
+      _ => 1,
+    };
+    self.pending = Some((observation, count));
+    if count != self.stable_frames {
+      return Ok(None);
+    }
+
+    let inferred = match &self.pending {
+      Some((Observation::Pieces(next), _)) => infer_move(&self.position, next),
+      Some((Observation::Occupancy(next), _)) => infer_move_from_occupancy(&self.position, *next),
+      None => return Ok(None),
+    };
+    let mv = match inferred {
+      Ok(mv) => mv,
+      Err(AmbiguousDiff::NoChange) => return Ok(None),
+      Err(err) => return Err(err.into()),
+    };
+
+    self.moves.push(RecordedMove {
+      mv,
+      san: self.position.san(&mv),
+      timestamp,
+    });
+    self.position = self.position.play(&mv);
+    return Ok(self.moves.last());
+  }
+
+  /// `1-0`, `0-1` or `1/2-1/2` once the game has ended by checkmate or stalemate, `*` while it
+  /// is in progress.
+  pub fn result(&self) -> &'static str {
+    if !self.position.legal_moves().is_empty() {
+      return "*";
+    }
+    if !self.position.in_check() {
+      return "1/2-1/2";
+    }
+    return match self.position.side_to_move {
+      PieceColor::White => "0-1",
+      PieceColor::Black => "1-0",
+    };
+  }
+
+  /// The recorded game in PGN. Timestamped moves carry the time spent on them as an `%emt`
+  /// comment.
+  pub fn pgn(&self) -> String {
+    let tag = |name: &str| {
+      let value = self.tags.iter().find(|(n, _)| n == name);
+      return value.map(|(_, value)| value.as_str());
+    };
+    let result = tag("Result").unwrap_or_else(|| self.result());
+    let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+
+    let mut pgn = String::new();
+    for (name, default) in ROSTER_TAGS.iter() {
+      let value = match *name {
+        "Result" => result,
+        _ => tag(name).unwrap_or(default),
+      };
+      pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+    }
+    for (name, value) in self.tags.iter() {
+      if !ROSTER_TAGS.iter().any(|(roster, _)| roster == name) {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+      }
+    }
+    let fen = self.start.to_fen();
+    if fen != STARTING_FEN {
+      pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", fen));
+    }
+    pgn.push('\n');
+
+    let mut tokens = Vec::new();
+    let mut position = self.start.clone();
+    let mut previous = Duration::from_secs(0);
+    // black moves need their number again when they do not directly follow white's move
+    let mut interrupted = true;
+    for recorded in self.moves.iter() {
+      match position.side_to_move {
+        PieceColor::White => tokens.push(format!("{}.", position.fullmove_number)),
+        PieceColor::Black if interrupted => tokens.push(format!("{}...", position.fullmove_number)),
+        PieceColor::Black => {}
+      }
+      tokens.push(recorded.san.clone());
+      interrupted = recorded.timestamp.is_some();
+      if let Some(timestamp) = recorded.timestamp {
+        let seconds = timestamp.saturating_sub(previous).as_secs();
+        tokens.push(format!(
+          "{{[%emt {}:{:02}:{:02}]}}",
+          seconds / 3600,
+          seconds / 60 % 60,
+          seconds % 60
+        ));
+        previous = timestamp;
+      }
+      position = position.play(&recorded.mv);
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+      if !line.is_empty() && line.len() + 1 + token.len() >= PGN_LINE_WIDTH {
+        pgn.push_str(&line);
+        pgn.push('\n');
+        line.clear();
+      }
+      if !line.is_empty() {
+        line.push(' ');
+      }
+      line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    return pgn;
+  }
+}
+
+#[test]
+fn should_record_pgn() {
+  let mut recorder = GameRecorder::default().with_stable_frames(2);
+  recorder.set_tag("White", "Player One");
+  let mut position = Position::starting();
+  for (index, uci) in ["f2f3", "e7e5", "g2g4", "d8h4"].iter().enumerate() {
+    let mv = position
+      .legal_moves()
+      .into_iter()
+      .find(|m| m.to_string() == *uci)
+      .unwrap();
+    position = position.play(&mv);
+    let timestamp = Some(Duration::from_secs(65 * (index as u64 + 1)));
+    // the first sighting of a new position is not trusted yet
+    assert_eq!(recorder.push_position(&position, timestamp).unwrap(), None);
+    assert!(recorder
+      .push_position(&position, timestamp)
+      .unwrap()
+      .is_some());
+    assert_eq!(recorder.push_position(&position, timestamp).unwrap(), None);
+  }
+
+  let sans: Vec<&str> = recorder.moves().iter().map(|m| m.san.as_str()).collect();
+  assert_eq!(sans, vec!["f3", "e5", "g4", "Qh4#"]);
+  assert_eq!(recorder.position(), &position);
+  assert_eq!(recorder.result(), "0-1");
+  let pgn = recorder.pgn();
+  assert!(pgn.contains("[White \"Player One\"]\n"));
+  assert!(pgn.contains("[Result \"0-1\"]\n"));
+  assert!(pgn.ends_with(
+    "\n1. f3 {[%emt 0:01:05]} 1... e5 {[%emt 0:01:05]} 2. g4 {[%emt 0:01:05]} 2...\nQh4# {[%emt 0:01:05]} 0-1\n"
+  ));
+}
+
+#[test]
+fn should_write_tag_roster_first() {
+  let mut recorder = GameRecorder::default();
+  recorder.set_tag("Annotator", "C:\\games \"club\"");
+  recorder.set_tag("Black", "Player Two");
+  recorder.set_tag("Result", "1-0");
+  let pgn = recorder.pgn();
+  let tags: Vec<&str> = pgn.lines().take_while(|line| !line.is_empty()).collect();
+  assert_eq!(
+    tags,
+    vec![
+      "[Event \"?\"]",
+      "[Site \"?\"]",
+      "[Date \"????.??.??\"]",
+      "[Round \"?\"]",
+      "[White \"?\"]",
+      "[Black \"Player Two\"]",
+      "[Result \"1-0\"]",
+      "[Annotator \"C:\\\\games \\\"club\\\"\"]",
+    ]
+  );
+  assert!(pgn.ends_with("\n\n1-0\n"));
+}
+
+#[test]
+fn should_record_from_occupancy() {
+  use crate::square::Square;
+
+  let mut recorder = GameRecorder::default().with_stable_frames(1);
+  let mut position = Position::starting();
+  for uci in ["e2e4", "d7d5", "e4d5"].iter() {
+    let mv = position
+      .legal_moves()
+      .into_iter()
+      .find(|m| m.to_string() == *uci)
+      .unwrap();
+    position = position.play(&mv);
+    recorder.push_occupancy(position.occupancy(), None).unwrap();
+  }
+  assert_eq!(recorder.pgn().lines().last(), Some("1. e4 d5 2. exd5 *"));
+
+  // with black to move the white pawn on d5 cannot turn up on h5
+  let mut impossible = position.occupancy();
+  impossible &= !(1 << "d5".parse::<Square>().unwrap().index());
+  impossible |= 1 << "h5".parse::<Square>().unwrap().index();
+  assert!(matches!(
+    recorder.push_occupancy(impossible, None),
+    Err(RecorderError::Diff(AmbiguousDiff::NoLegalMove(_)))
+  ));
+}
@@ -1,17 +1,24 @@
 // https://github.com/Elucidation/ChessboardDetect/blob/master/Brutesac.py
 
 use crate::delaunay_triangulation::{triangulate, Triangulation, EMPTY};
+use crate::homography::fit_projection;
+use crate::indexing::index_lattice;
 use crate::lattice::is_corner;
 use crate::line::intersection;
 use crate::point::Point;
+use crate::segmentation::LATTICE_SIZE;
 use image::GrayImage;
+use imageproc::geometric_transformations::Projection;
 use std::collections::HashSet;
 
+// sine of the smallest angle a quad may turn through at any of its corners
+const MIN_TURN: f64 = 0.1;
+
 fn edges_of_triangle(t: usize) -> [usize; 3] {
   return [3 * t, 3 * t + 1, 3 * t + 2];
 }
 
-fn points_of_triangle(tri: &Triangulation, points: &Vec<Point>, t: usize) -> [Point; 3] {
+fn points_of_triangle(tri: &Triangulation, points: &[Point], t: usize) -> [Point; 3] {
   let edges = edges_of_triangle(t);
   return [
     points[tri.triangles[edges[0]]],
@@ -27,8 +34,8 @@ fn triangle_of_edge(e: usize) -> usize {
 fn neighbors_of_triangle(tri: &Triangulation, t: usize) -> Vec<usize> {
   let mut neighbors = Vec::new();
   let edges = edges_of_triangle(t);
-  for e in 0..edges.len() {
-    let opposite = tri.halfedges[edges[e]];
+  for edge in edges.iter() {
+    let opposite = tri.halfedges[*edge];
     if opposite != EMPTY {
       let t = triangle_of_edge(opposite);
       neighbors.push(t);
@@ -52,6 +59,17 @@ fn quad_sort(quad: [Point; 4]) -> [Point; 4] {
   return [top[0], top[1], bottom[1], bottom[0]];
 }
 
+fn to_tuple(point: Point) -> (f32, f32) {
+  return (point.x as f32, point.y as f32);
+}
+
+// whether `point`, on the line through `start` and `end`, lies between them
+fn on_segment(point: (f32, f32), start: Point, end: Point) -> bool {
+  let (start, end) = (to_tuple(start), to_tuple(end));
+  let within = |value: f32, a: f32, b: f32| value >= a.min(b) && value <= a.max(b);
+  return within(point.0, start.0, end.0) && within(point.1, start.1, end.1);
+}
+
 fn quad_edges_intersect(quad: [Point; 4]) -> bool {
   let mut edges = Vec::new();
   for start in 0..4 {
@@ -74,13 +92,17 @@ fn quad_edges_intersect(quad: [Point; 4]) -> bool {
         continue;
       }
 
-      if let Some(_int) = intersection(
-        (a_start.x as f32, a_start.y as f32),
-        (a_end.x as f32, a_end.y as f32),
-        (b_start.x as f32, b_start.y as f32),
-        (b_end.x as f32, b_end.y as f32),
+      // the lines through the edges meet unless they are parallel, the edges only when that
+      // point lies on both of them
+      if let Some(point) = intersection(
+        to_tuple(a_start),
+        to_tuple(a_end),
+        to_tuple(b_start),
+        to_tuple(b_end),
       ) {
-        return true;
+        if on_segment(point, a_start, a_end) && on_segment(point, b_start, b_end) {
+          return true;
+        }
       }
     }
   }
@@ -88,7 +110,23 @@ fn quad_edges_intersect(quad: [Point; 4]) -> bool {
   return false;
 }
 
-fn quads(points: &Vec<Point>) -> Vec<[Point; 4]> {
+// whether every corner of `quad` turns the same way, and by more than a sliver, so that no
+// three of its points are close to a straight line
+fn quad_is_convex(quad: [Point; 4]) -> bool {
+  let mut turns = [0.0; 4];
+  for k in 0..4 {
+    let a = quad[(k + 1) % 4] - quad[k];
+    let b = quad[(k + 2) % 4] - quad[(k + 1) % 4];
+    let length = (a.x * a.x + a.y * a.y).sqrt() * (b.x * b.x + b.y * b.y).sqrt();
+    if length == 0.0 {
+      return false;
+    }
+    turns[k] = (a.x * b.y - a.y * b.x) / length;
+  }
+  return turns.iter().all(|turn| *turn > MIN_TURN) || turns.iter().all(|turn| *turn < -MIN_TURN);
+}
+
+fn quads(points: &[Point]) -> Vec<[Point; 4]> {
   if let Some(tri) = triangulate(points) {
     let mut quads: Vec<[Point; 4]> = Vec::new();
     let mut seen: HashSet<(usize, usize)> = HashSet::new();
@@ -101,10 +139,10 @@ fn quads(points: &Vec<Point>) -> Vec<[Point; 4]> {
           let neighbor = points_of_triangle(&tri, points, neighbor_index);
           let mut quad = triangle.to_vec();
           quad.extend(neighbor.to_vec());
-          quad.sort_by(|a, b| a.partial_cmp(&b).unwrap());
+          quad.sort_by(|a, b| a.partial_cmp(b).unwrap());
           quad.dedup();
           let sorted_quad = quad_sort([quad[0], quad[1], quad[2], quad[3]]);
-          if !quad_edges_intersect(sorted_quad) {
+          if quad_is_convex(sorted_quad) && !quad_edges_intersect(sorted_quad) {
             quads.push(sorted_quad);
           }
           seen.insert((triangle_index, neighbor_index));
@@ -117,102 +155,236 @@ fn quads(points: &Vec<Point>) -> Vec<[Point; 4]> {
   return Vec::new();
 }
 
-fn sample_points() -> Vec<Point> {
+// the grid is sampled this many squares either side of the seed square, enough to reach
+// across the whole board from any one of its squares
+const SAMPLE_RADIUS: i32 = LATTICE_SIZE as i32 - 1;
+
+// points within this many squares of a grid corner lie on the grid
+const INLIER_DISTANCE: f32 = 0.2;
+
+// refits of the projection to the inliers of the one before
+const REFINEMENTS: usize = 3;
+
+/// A square grid fitted to corner points.
+#[derive(Clone, Debug)]
+pub struct GridFit {
+  /// Maps image coordinates onto grid coordinates, in which neighbouring corners are one apart
+  /// and the seed square spans `(0, 0)` to `(1, 1)`.
+  pub projection: Projection,
+
+  /// The sampled grid corners in image coordinates.
+  pub lattice: Vec<Point>,
+
+  /// The input points that lie on the grid.
+  pub inliers: Vec<Point>,
+
+  /// Grid coordinates of every point in `inliers`.
+  pub coordinates: Vec<(i32, i32)>,
+
+  /// Number of points in `lattice` that look like corners in the image.
+  pub score: usize,
+}
+
+fn sample_points() -> Vec<(i32, i32)> {
   let mut sample_points = Vec::new();
-  for y in 0..14 {
-    for x in 0..14 {
-      sample_points.push(Point {
-        x: x as f64 - 7.0,
-        y: y as f64 - 7.0,
-      });
+  for y in -SAMPLE_RADIUS..=SAMPLE_RADIUS {
+    for x in -SAMPLE_RADIUS..=SAMPLE_RADIUS {
+      sample_points.push((x, y));
     }
   }
   return sample_points;
 }
 
-fn transform_sample_points(
-  sample_points: &Vec<Point>,
-  i: &Point,
-  j: &Point,
-  t: &Point,
-) -> Vec<Point> {
-  let mut transformed_sample_points = Vec::new();
-  for sample_point in sample_points.iter() {
-    transformed_sample_points.push(Point {
-      x: sample_point.x * i.x + sample_point.y * j.x + t.x,
-      y: sample_point.x * i.y + sample_point.y * j.y + t.y,
-    });
+// grid points mapped into the image by the inverse of `projection`
+fn transform_sample_points(sample_points: &[(i32, i32)], projection: &Projection) -> Vec<Point> {
+  let inverse = projection.invert();
+  return sample_points
+    .iter()
+    .map(|(x, y)| Point::from(inverse * (*x as f32, *y as f32)))
+    .collect();
+}
+
+// the points within `INLIER_DISTANCE` of a grid corner, along with that corner
+fn inliers(points: &[Point], projection: &Projection) -> (Vec<Point>, Vec<(i32, i32)>) {
+  let mut inliers = Vec::new();
+  let mut coordinates = Vec::new();
+  for point in points.iter() {
+    let (x, y) = *projection * to_tuple(*point);
+    let (column, row) = (x.round(), y.round());
+    if ((x - column).powf(2.0) + (y - row).powf(2.0)).sqrt() <= INLIER_DISTANCE {
+      inliers.push(*point);
+      coordinates.push((column as i32, row as i32));
+    }
   }
-  return transformed_sample_points;
+  return (inliers, coordinates);
 }
 
-pub fn grid(image: &GrayImage, points: &Vec<Point>) {
-  let quads = quads(points);
+// re-indexes the grid so that its unit steps are the edges of a square, pointing right and
+// down as far as possible. A quad of two half squares predicts the same corners as a square,
+// but steps diagonally across the board
+fn reduce_basis(projection: Projection, inliers: &[Point]) -> Option<Projection> {
+  if inliers.is_empty() {
+    return Some(projection);
+  }
+  let count = inliers.len() as f32;
+  let centroid = (
+    inliers.iter().map(|p| p.x as f32).sum::<f32>() / count,
+    inliers.iter().map(|p| p.y as f32).sum::<f32>() / count,
+  );
+  let (gx, gy) = projection * centroid;
+  let inverse = projection.invert();
+  let origin = inverse * (gx, gy);
+  let step = |(x, y): (i32, i32)| {
+    let (ix, iy) = inverse * (gx + x as f32, gy + y as f32);
+    (ix - origin.0, iy - origin.1)
+  };
+  let length = |(x, y): (f32, f32)| x * x + y * y;
+
+  // gauss reduction of the two steps as they appear around the middle of the grid
+  let (mut u1, mut u2) = ((1, 0), (0, 1));
+  for _ in 0..32 {
+    if length(step(u1)) > length(step(u2)) {
+      std::mem::swap(&mut u1, &mut u2);
+    }
+    let (e1, e2) = (step(u1), step(u2));
+    let m = ((e1.0 * e2.0 + e1.1 * e2.1) / length(e1)).round() as i32;
+    if m == 0 {
+      break;
+    }
+    u2 = (u2.0 - m * u1.0, u2.1 - m * u1.1);
+  }
+
+  if step(u1).0.abs() < step(u2).0.abs() {
+    std::mem::swap(&mut u1, &mut u2);
+  }
+  if step(u1).0 < 0.0 {
+    u1 = (-u1.0, -u1.1);
+  }
+  let (e1, e2) = (step(u1), step(u2));
+  if e1.0 * e2.1 - e1.1 * e2.0 < 0.0 {
+    u2 = (-u2.0, -u2.1);
+  }
+
+  // the new coordinates are the old ones in terms of u1 and u2
+  let determinant = (u1.0 * u2.1 - u1.1 * u2.0) as f32;
+  let to_reduced = Projection::from_matrix([
+    u2.1 as f32 / determinant,
+    -u2.0 as f32 / determinant,
+    0.0,
+    -u1.1 as f32 / determinant,
+    u1.0 as f32 / determinant,
+    0.0,
+    0.0,
+    0.0,
+    1.0,
+  ])?;
+  return Some(to_reduced * projection);
+}
 
-  let mut best_points = Vec::new();
-  let mut best_transformed = Vec::new();
+/// Finds the square grid that best explains `points`, after Brutesac: every pair of
+/// neighbouring Delaunay triangles is tried as a single square, the grid it implies is sampled
+/// and scored by how many of its corners look like corners in `image`, and the projection of
+/// the best one is refitted to the points that lie on it.
+pub fn fit_grid(image: &GrayImage, points: &[Point]) -> Option<GridFit> {
+  let quads = quads(points);
   let sample_points = sample_points();
-  for n in 0..quads.len() {
-    let quad = quads[n];
-    let i = quad[1] - quad[0];
-    let j = quad[3] - quad[0];
-    let transformed_sample_points = transform_sample_points(&sample_points, &i, &j, &quad[0]);
-    let mut corner_points = Vec::new();
-    for i in 0..transformed_sample_points.len() {
-      let point = transformed_sample_points[i];
-      if is_corner(image, point.x as u32, point.y as u32) {
-        corner_points.push(point);
-      }
+  let unit_square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+
+  let corners_on = |lattice: &[Point]| {
+    lattice
+      .iter()
+      .filter(|point| point.x >= 0.0 && point.y >= 0.0)
+      .filter(|point| is_corner(image, point.x as u32, point.y as u32))
+      .count()
+  };
+
+  // a quad spanning more than one square or cutting across one predicts a grid that misses
+  // many of the points, the image breaks ties
+  let mut best: Option<([Point; 4], Projection, (usize, usize))> = None;
+  for quad in quads.iter() {
+    let projection = match fit_projection(&quad.map(to_tuple), &unit_square) {
+      Some(projection) => projection,
+      None => continue,
+    };
+    let matched = inliers(points, &projection).0.len();
+    if best.is_some_and(|(_, _, (best_matched, _))| matched < best_matched) {
+      continue;
     }
-    if corner_points.len() > best_points.len() {
-      best_points = corner_points;
-      best_transformed = transformed_sample_points;
+    let score = (
+      matched,
+      corners_on(&transform_sample_points(&sample_points, &projection)),
+    );
+    if best.is_none_or(|(_, _, best_score)| score > best_score) {
+      best = Some((*quad, projection, score));
     }
-    // if n == 1 {
-    //   best_points = corner_points;
-    //   best_transformed = transformed_sample_points;
-    // }
   }
+  let (quad, mut projection, _) = best?;
+
+  // a single square only roughly predicts corners far from it, walking the lattice out from it
+  // follows any bend in the grid
+  let tuples: Vec<(f32, f32)> = points.iter().map(|point| to_tuple(*point)).collect();
+  let index = index_lattice(&tuples, quad.map(to_tuple));
+  let mut from = Vec::new();
+  let mut to = Vec::new();
+  for (point, coordinate) in tuples.iter().zip(index.coordinates().iter()) {
+    if let Some((x, y)) = coordinate {
+      from.push(*point);
+      to.push((*x as f32, *y as f32));
+    }
+  }
+  if let Some(walked) = fit_projection(&from, &to) {
+    projection = walked;
+  }
+
+  for _ in 0..REFINEMENTS {
+    let (inliers, coordinates) = inliers(points, &projection);
+    let to: Vec<(f32, f32)> = coordinates
+      .iter()
+      .map(|(x, y)| (*x as f32, *y as f32))
+      .collect();
+    let from: Vec<(f32, f32)> = inliers.iter().map(|point| to_tuple(*point)).collect();
+    match fit_projection(&from, &to) {
+      Some(refined) => projection = refined,
+      None => break,
+    }
+  }
+  let projection = reduce_basis(projection, &inliers(points, &projection).0)?;
+  let (inliers, coordinates) = inliers(points, &projection);
+  if inliers.len() < 4 {
+    return None;
+  }
+  let lattice = transform_sample_points(&sample_points, &projection);
+  let score = corners_on(&lattice);
 
   if crate::debug::debug_images() {
     let mut quads_image = image::DynamicImage::ImageLuma8(image.clone()).to_rgb8();
-    for i in 0..best_transformed.len() {
-      let point = best_transformed[i];
-      let c = image::Rgb::<u8>([0, 255, 0]);
+    for point in lattice.iter() {
       imageproc::drawing::draw_filled_circle_mut(
         &mut quads_image,
         (point.x as i32, point.y as i32),
         3,
-        c,
+        image::Rgb::<u8>([0, 255, 0]),
       );
     }
-    for i in 0..best_points.len() {
-      let point = best_points[i];
-      let c = image::Rgb::<u8>([255, 0, 0]);
+    for point in inliers.iter() {
       imageproc::drawing::draw_filled_circle_mut(
         &mut quads_image,
         (point.x as i32, point.y as i32),
         3,
-        c,
+        image::Rgb::<u8>([255, 0, 0]),
       );
     }
-    //     for qi in 0..quads.len() {
-    //         let quad = quads[qi];
-    //         println!("{:?}", quad);
-    //         let [r, g, b] = color::turbo(qi as f32 / quads.len() as f32);
-    //         let c = image::Rgb::<u8>([r, b, g]);
-    //         for n in 0..quad.len() {
-    //             let next = (n + 1) % quad.len();
-    //             imageproc::drawing::draw_line_segment_mut(
-    //                 &mut quads_image,
-    //                 (quad[n].x as f32, quad[n].y as f32),
-    //                 (quad[next].x as f32, quad[next].y as f32),
-    //                 c,
-    //             );
-    //         }
-    //     }
-    crate::debug::write_rgb(&quads_image, "quads");
+    // debug output is best effort, the fit stands without it
+    crate::debug::write_rgb(&quads_image, "quads").ok();
   }
+
+  return Some(GridFit {
+    projection,
+    lattice,
+    inliers,
+    coordinates,
+    score,
+  });
 }
 
 #[test]
@@ -251,3 +423,43 @@ fn should_find_two_quads() {
   //   assert_eq!(quads[0][i], points[i]);
   // }
 }
+
+#[test]
+fn should_fit_grid_to_lattice_corners() {
+  use crate::checkerboard::Checkerboard;
+
+  let corners = [(70.0, 60.0), (330.0, 80.0), (350.0, 340.0), (50.0, 320.0)];
+  let board = Checkerboard {
+    dark: image::Rgb([60, 60, 60]),
+    light: image::Rgb([200, 200, 200]),
+    ..Checkerboard::through(corners)
+  };
+  let image = board.gray(400, 400);
+  let from_board = board.to_board.invert();
+
+  // the inner corners slightly off, plus a few strays between them
+  let mut points: Vec<Point> = Vec::new();
+  for row in 1..8 {
+    for column in 1..8 {
+      let (x, y) = from_board * (column as f32, row as f32);
+      let jitter = ((row * 7 + column * 3) % 5) as f32 * 0.2 - 0.4;
+      points.push(Point::from((x + jitter, y - jitter)));
+    }
+  }
+  for stray in [(-1.5, 4.5), (9.5, -0.5), (4.5, 4.5)].iter() {
+    points.push(Point::from(from_board * *stray));
+  }
+
+  let fit = fit_grid(&image, &points).unwrap();
+  assert_eq!(fit.inliers.len(), 49);
+  assert!(fit.score >= 49);
+
+  // the grid steps along the board edges, in the same direction as the board
+  let (column, row) = fit.coordinates[0];
+  for (index, coordinate) in fit.coordinates.iter().enumerate() {
+    assert_eq!(
+      *coordinate,
+      (column + (index % 7) as i32, row + (index / 7) as i32)
+    );
+  }
+}
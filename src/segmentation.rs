@@ -0,0 +1,155 @@
+use crate::orientation::{detect_rotation, Rotation};
+use crate::square::Square;
+use image::imageops::crop_imm;
+use image::RgbImage;
+use imageproc::geometric_transformations::Projection;
+
+/// Number of square corners along each edge of the board, including the outer border.
+pub const LATTICE_SIZE: usize = 9;
+
+/// Result of locating a chess board within an image.
+#[derive(Clone, Debug)]
+pub struct BoardSegmentation {
+  /// The four outer board corners in source image coordinates, in the order they map onto
+  /// the top-left, top-right, bottom-right and bottom-left corners of `board`.
+  pub corners: [(f32, f32); 4],
+
+  /// Maps source image coordinates onto `board` coordinates.
+  pub projection: Projection,
+
+  /// The board warped to an axis-aligned square image.
+  pub board: RgbImage,
+
+  /// Square corners in source image coordinates, indexed `[row][column]` where row 0 is the
+  /// top edge and column 0 the left edge of `board`.
+  pub lattice: [[(f32, f32); LATTICE_SIZE]; LATTICE_SIZE],
+
+  /// How the chess coordinate system is rotated within `board`.
+  pub rotation: Rotation,
+}
+
+impl BoardSegmentation {
+  pub fn new(corners: [(f32, f32); 4], projection: Projection, board: RgbImage) -> Self {
+    let rotation = detect_rotation(&board);
+    return BoardSegmentation::with_rotation(corners, projection, board, rotation);
+  }
+
+  // as `new` for a board whose rotation is already known
+  pub(crate) fn with_rotation(
+    corners: [(f32, f32); 4],
+    projection: Projection,
+    board: RgbImage,
+    rotation: Rotation,
+  ) -> Self {
+    let lattice = lattice_from_projection(&projection, board.dimensions());
+    BoardSegmentation {
+      corners,
+      projection,
+      board,
+      lattice,
+      rotation,
+    }
+  }
+
+  /// Side length of a single square in `board` pixels.
+  pub fn square_size(&self) -> (f32, f32) {
+    let (width, height) = self.board.dimensions();
+    let squares = (LATTICE_SIZE - 1) as f32;
+    return (width as f32 / squares, height as f32 / squares);
+  }
+
+  /// Row and column of `square` within `board`.
+  pub fn square_cell(&self, square: Square) -> (u32, u32) {
+    return self.rotation.cell(square);
+  }
+
+  /// Pixel bounds `(x, y, width, height)` of `square` within `board`, shrunk on every side by
+  /// `margin` as a fraction of the square size.
+  pub fn square_bounds(&self, square: Square, margin: f32) -> (u32, u32, u32, u32) {
+    let (row, column) = self.square_cell(square);
+    let (square_width, square_height) = self.square_size();
+    let margin = margin.clamp(0.0, 0.49);
+    let left = (column as f32 + margin) * square_width;
+    let top = (row as f32 + margin) * square_height;
+    let right = (column as f32 + 1.0 - margin) * square_width;
+    let bottom = (row as f32 + 1.0 - margin) * square_height;
+    let x = left.round() as u32;
+    let y = top.round() as u32;
+    return (
+      x,
+      y,
+      (right.round() as u32).saturating_sub(x).max(1),
+      (bottom.round() as u32).saturating_sub(y).max(1),
+    );
+  }
+
+  /// Crop of a single square from `board`, see `square_bounds` for `margin`.
+  pub fn square(&self, square: Square, margin: f32) -> RgbImage {
+    let (x, y, width, height) = self.square_bounds(square, margin);
+    return crop_imm(&self.board, x, y, width, height).to_image();
+  }
+
+  /// Crops of all 64 squares from a1 to h8.
+  pub fn squares(&self, margin: f32) -> Vec<(Square, RgbImage)> {
+    return Square::all()
+      .map(|square| (square, self.square(square, margin)))
+      .collect();
+  }
+}
+
+// projects an evenly spaced grid over the rectified board back into the source image
+fn lattice_from_projection(
+  projection: &Projection,
+  (width, height): (u32, u32),
+) -> [[(f32, f32); LATTICE_SIZE]; LATTICE_SIZE] {
+  let inverse = projection.invert();
+  let squares = (LATTICE_SIZE - 1) as f32;
+  let mut lattice = [[(0.0, 0.0); LATTICE_SIZE]; LATTICE_SIZE];
+  for (row, points) in lattice.iter_mut().enumerate() {
+    for (column, point) in points.iter_mut().enumerate() {
+      let x = column as f32 * width as f32 / squares;
+      let y = row as f32 * height as f32 / squares;
+      *point = inverse * (x, y);
+    }
+  }
+  return lattice;
+}
+
+#[test]
+fn should_project_lattice_to_source_coordinates() {
+  let projection = Projection::scale(0.5, 0.5);
+  let segmentation = BoardSegmentation::new(
+    [(0.0, 0.0), (800.0, 0.0), (800.0, 800.0), (0.0, 800.0)],
+    projection,
+    RgbImage::new(400, 400),
+  );
+
+  assert_eq!(segmentation.square_size(), (50.0, 50.0));
+  assert_eq!(segmentation.lattice[0][0], (0.0, 0.0));
+  assert_eq!(segmentation.lattice[0][8], (800.0, 0.0));
+  assert_eq!(segmentation.lattice[4][2], (200.0, 400.0));
+  assert_eq!(segmentation.lattice[8][8], (800.0, 800.0));
+}
+
+#[test]
+fn should_crop_squares_with_margin() {
+  let board = RgbImage::from_fn(400, 400, |x, y| image::Rgb([(x / 50) as u8, (y / 50) as u8, 0]));
+  let mut segmentation = BoardSegmentation::new(
+    [(0.0, 0.0), (400.0, 0.0), (400.0, 400.0), (0.0, 400.0)],
+    Projection::scale(1.0, 1.0),
+    board,
+  );
+  segmentation.rotation = Rotation::Rotate0;
+
+  let squares = segmentation.squares(0.1);
+  assert_eq!(squares.len(), 64);
+  for (square, crop) in squares.iter() {
+    assert_eq!(crop.dimensions(), (40, 40));
+    let (row, column) = segmentation.square_cell(*square);
+    assert!(crop
+      .pixels()
+      .all(|p| p[0] as u32 == column && p[1] as u32 == row));
+  }
+  assert_eq!(squares[0].0.to_string(), "a1");
+  assert_eq!(segmentation.square_cell(squares[0].0), (7, 0));
+}
@@ -0,0 +1,116 @@
+// Steadies the board corners found in consecutive frames of a static or slowly moving camera.
+
+const DEFAULT_PROCESS_NOISE: f32 = 1.0;
+const DEFAULT_MEASUREMENT_NOISE: f32 = 4.0;
+
+// a corner that jumps further than this is assumed to follow a real move of the board
+const DEFAULT_RESET_DISTANCE: f32 = 20.0;
+
+/// Kalman filter over the four board corners.
+///
+/// Every coordinate is modelled as a random walk: between frames it drifts with variance
+/// `process_noise` and every measurement is off by variance `measurement_noise`, both in
+/// squared pixels. A lower ratio of process to measurement noise gives steadier but slower
+/// corners.
+#[derive(Clone, Debug)]
+pub struct CornerFilter {
+  process_noise: f32,
+  measurement_noise: f32,
+  reset_distance: f32,
+  state: Option<[(f32, f32); 4]>,
+  variance: f32,
+}
+
+impl Default for CornerFilter {
+  fn default() -> Self {
+    CornerFilter::new(DEFAULT_PROCESS_NOISE, DEFAULT_MEASUREMENT_NOISE)
+  }
+}
+
+impl CornerFilter {
+  pub fn new(process_noise: f32, measurement_noise: f32) -> Self {
+    CornerFilter {
+      process_noise: process_noise.max(0.0),
+      measurement_noise: measurement_noise.max(f32::EPSILON),
+      reset_distance: DEFAULT_RESET_DISTANCE,
+      state: None,
+      variance: 0.0,
+    }
+  }
+
+  /// Distance in pixels beyond which a single corner moving restarts the filter, so a board
+  /// that is picked up and put down does not slowly slide into place.
+  pub fn with_reset_distance(mut self, distance: f32) -> Self {
+    self.reset_distance = distance;
+    return self;
+  }
+
+  /// The current estimate, `None` until the first measurement.
+  pub fn corners(&self) -> Option<[(f32, f32); 4]> {
+    return self.state;
+  }
+
+  pub fn reset(&mut self) {
+    self.state = None;
+  }
+
+  /// Folds in the corners measured in the next frame and returns the new estimate.
+  pub fn update(&mut self, measured: [(f32, f32); 4]) -> [(f32, f32); 4] {
+    let mut state = match self.state {
+      Some(state) => state,
+      None => return self.restart(measured),
+    };
+    let jumped = state
+      .iter()
+      .zip(measured.iter())
+      .any(|(s, m)| ((s.0 - m.0).powf(2.0) + (s.1 - m.1).powf(2.0)).sqrt() > self.reset_distance);
+    if jumped {
+      return self.restart(measured);
+    }
+
+    // all coordinates share the same variance as they see the same noise
+    let predicted_variance = self.variance + self.process_noise;
+    let gain = predicted_variance / (predicted_variance + self.measurement_noise);
+    for (s, m) in state.iter_mut().zip(measured.iter()) {
+      s.0 += gain * (m.0 - s.0);
+      s.1 += gain * (m.1 - s.1);
+    }
+    self.variance = (1.0 - gain) * predicted_variance;
+    self.state = Some(state);
+    return state;
+  }
+
+  fn restart(&mut self, measured: [(f32, f32); 4]) -> [(f32, f32); 4] {
+    self.state = Some(measured);
+    self.variance = self.measurement_noise;
+    return measured;
+  }
+}
+
+#[test]
+fn should_smooth_jittering_corners() {
+  let truth = [(50.0, 40.0), (350.0, 45.0), (345.0, 360.0), (55.0, 355.0)];
+  let jitter = [2.0, -1.5, 1.0, -2.0, 1.5, -1.0, 2.0, -2.0];
+  let mut filter = CornerFilter::default();
+
+  let mut raw_error = 0.0;
+  let mut smoothed_error = 0.0;
+  for frame in 0..40 {
+    let offset = jitter[frame % jitter.len()];
+    let mut measured = truth;
+    for corner in measured.iter_mut() {
+      corner.0 += offset;
+      corner.1 -= offset;
+    }
+    let smoothed = filter.update(measured);
+    if frame >= 10 {
+      raw_error += offset.abs();
+      smoothed_error += (smoothed[0].0 - truth[0].0).abs();
+    }
+  }
+  assert!(smoothed_error < raw_error / 2.0);
+
+  // a large move is followed immediately
+  let moved = [(150.0, 40.0), (450.0, 45.0), (445.0, 360.0), (155.0, 355.0)];
+  assert_eq!(filter.update(moved), moved);
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+/// Buckets points into square cells so that the neighbours of a point are found by looking at
+/// the cells around it instead of at every point.
+///
+/// Queries refer to points by their index in the slice the index was built from, so duplicate
+/// coordinates stay distinct.
+#[derive(Clone, Debug)]
+pub struct GridIndex<'a> {
+  points: &'a [(f32, f32)],
+  cell_size: f32,
+  cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl<'a> GridIndex<'a> {
+  /// Queries are cheapest when `cell_size` is close to the radius they use.
+  pub fn new(points: &'a [(f32, f32)], cell_size: f32) -> Self {
+    let cell_size = if cell_size > 0.0 { cell_size } else { 1.0 };
+    let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (index, point) in points.iter().enumerate() {
+      cells
+        .entry(cell_of(*point, cell_size))
+        .or_default()
+        .push(index);
+    }
+    GridIndex {
+      points,
+      cell_size,
+      cells,
+    }
+  }
+
+  /// Indices of every point no further than `radius` from `point`, including the point itself
+  /// when it is part of the index, in ascending order.
+  pub fn within(&self, point: (f32, f32), radius: f32) -> Vec<usize> {
+    let radius_squared = radius * radius;
+    let rings = (radius / self.cell_size).ceil().max(0.0) as i32;
+    let (cx, cy) = cell_of(point, self.cell_size);
+    let mut found = Vec::new();
+    for y in cy - rings..=cy + rings {
+      for x in cx - rings..=cx + rings {
+        if let Some(indices) = self.cells.get(&(x, y)) {
+          for index in indices.iter() {
+            let (px, py) = self.points[*index];
+            if (px - point.0).powf(2.0) + (py - point.1).powf(2.0) <= radius_squared {
+              found.push(*index);
+            }
+          }
+        }
+      }
+    }
+    found.sort_unstable();
+    return found;
+  }
+}
+
+fn cell_of((x, y): (f32, f32), cell_size: f32) -> (i32, i32) {
+  return (
+    (x / cell_size).floor() as i32,
+    (y / cell_size).floor() as i32,
+  );
+}
+
+#[test]
+fn should_find_points_within_radius() {
+  let points: Vec<(f32, f32)> = (0..500)
+    .map(|i| (((i * 37) % 400) as f32 * 0.9, ((i * 91) % 400) as f32 * 0.7))
+    .chain(vec![(10.0, 10.0), (10.0, 10.0)])
+    .collect();
+  let index = GridIndex::new(&points, 12.0);
+  for query in [(10.0, 10.0), (200.0, 150.0), (-5.0, 300.0)].iter() {
+    for radius in [0.0, 5.0, 12.0, 40.0].iter() {
+      let expected: Vec<usize> = (0..points.len())
+        .filter(|i| {
+          let (x, y) = points[*i];
+          (x - query.0).powf(2.0) + (y - query.1).powf(2.0) <= radius * radius
+        })
+        .collect();
+      assert_eq!(index.within(*query, *radius), expected);
+    }
+  }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of files (and ranks) on a chess board.
+pub const BOARD_WIDTH: u8 = 8;
+
+const FILE_NAMES: &[u8; 8] = b"abcdefgh";
+
+/// A square on the chess board, addressed by zero-based file (a..h) and rank (1..8).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square {
+  rank: u8,
+  file: u8,
+}
+
+impl Square {
+  /// Returns `None` if either coordinate is off the board.
+  pub fn new(file: u8, rank: u8) -> Option<Square> {
+    if file >= BOARD_WIDTH || rank >= BOARD_WIDTH {
+      return None;
+    }
+    return Some(Square { file, rank });
+  }
+
+  /// Square at `index` counting from a1 = 0 along each rank to h8 = 63.
+  pub fn from_index(index: usize) -> Option<Square> {
+    let width = BOARD_WIDTH as usize;
+    if index >= width * width {
+      return None;
+    }
+    return Square::new((index % width) as u8, (index / width) as u8);
+  }
+
+  /// All 64 squares in order a1, b1, .., h1, a2, .., h8.
+  pub fn all() -> impl Iterator<Item = Square> {
+    let width = BOARD_WIDTH as usize;
+    return (0..width * width).filter_map(Square::from_index);
+  }
+
+  pub fn file(&self) -> u8 {
+    return self.file;
+  }
+
+  pub fn rank(&self) -> u8 {
+    return self.rank;
+  }
+
+  pub fn index(&self) -> usize {
+    return self.rank as usize * BOARD_WIDTH as usize + self.file as usize;
+  }
+
+  /// Light squares are those where file and rank differ in parity, so h1 is light.
+  pub fn is_light(&self) -> bool {
+    return (self.file + self.rank) % 2 == 1;
+  }
+
+  /// The square offset by `(files, ranks)`, or `None` if that leaves the board.
+  pub fn offset(&self, files: i8, ranks: i8) -> Option<Square> {
+    let file = self.file as i8 + files;
+    let rank = self.rank as i8 + ranks;
+    if file < 0 || rank < 0 {
+      return None;
+    }
+    return Square::new(file as u8, rank as u8);
+  }
+}
+
+impl fmt::Display for Square {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}{}", FILE_NAMES[self.file as usize] as char, self.rank + 1)
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseSquareError;
+
+impl fmt::Display for ParseSquareError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "invalid square name")
+  }
+}
+
+impl std::error::Error for ParseSquareError {}
+
+impl FromStr for Square {
+  type Err = ParseSquareError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 2 {
+      return Err(ParseSquareError);
+    }
+    let file = FILE_NAMES
+      .iter()
+      .position(|f| *f == bytes[0])
+      .ok_or(ParseSquareError)?;
+    if bytes[1] < b'1' || bytes[1] > b'8' {
+      return Err(ParseSquareError);
+    }
+    return Square::new(file as u8, bytes[1] - b'1').ok_or(ParseSquareError);
+  }
+}
+
+#[test]
+fn should_name_squares() {
+  let names: Vec<String> = Square::all().map(|s| s.to_string()).collect();
+  assert_eq!(names.len(), 64);
+  assert_eq!(names[0], "a1");
+  assert_eq!(names[7], "h1");
+  assert_eq!(names[63], "h8");
+  assert_eq!("e4".parse(), Ok(Square::new(4, 3).unwrap()));
+  assert_eq!("i1".parse::<Square>(), Err(ParseSquareError));
+  assert!("h1".parse::<Square>().unwrap().is_light());
+  assert!(!"a1".parse::<Square>().unwrap().is_light());
+}
@@ -0,0 +1,277 @@
+// Follows a board from frame to frame by looking for its square corners near where the
+// previous frame predicts them, which is much cheaper and steadier than detecting it anew.
+
+use crate::error::SegmentError;
+use crate::homography::{fit_projection, Ransac};
+use crate::lattice::{corner_response, refine_corner};
+use crate::segmentation::{BoardSegmentation, LATTICE_SIZE};
+use crate::smoothing::CornerFilter;
+use image::{DynamicImage, GenericImageView, GrayImage};
+use imageproc::geometric_transformations::Projection;
+
+// corners are searched at most this many working pixels from their predicted position
+const DEFAULT_SEARCH_RADIUS: f32 = 8.0;
+
+// fraction of inner lattice corners that must be found to keep tracking
+const DEFAULT_MIN_CONFIDENCE: f32 = 0.5;
+
+// a corner whose response is below this is taken to be noise in a flat region
+const MIN_CORNER_RESPONSE: f32 = 100.0;
+
+// corners that land further than this many board pixels from where the fitted projection puts
+// them are left out of the fit
+const MAX_REPROJECTION_ERROR: f32 = 2.0;
+
+/// Follows a board through a stream of frames of the same size.
+///
+/// Each frame the square corners are looked for near the lattice of the previous frame and
+/// the projection is refitted to the ones that are found. When too few are found the board is
+/// detected from scratch with `segment`. The corners can additionally be smoothed over time,
+/// see `with_smoothing`.
+#[derive(Clone, Debug)]
+pub struct BoardTracker {
+  last: Option<BoardSegmentation>,
+  confidence: f32,
+  tracked: bool,
+  search_radius: f32,
+  min_confidence: f32,
+  smoothing: Option<CornerFilter>,
+}
+
+impl Default for BoardTracker {
+  fn default() -> Self {
+    BoardTracker::new()
+  }
+}
+
+impl BoardTracker {
+  pub fn new() -> Self {
+    BoardTracker {
+      last: None,
+      confidence: 0.0,
+      tracked: false,
+      search_radius: DEFAULT_SEARCH_RADIUS,
+      min_confidence: DEFAULT_MIN_CONFIDENCE,
+      smoothing: None,
+    }
+  }
+
+  /// Furthest a corner is searched from its predicted position, in pixels of the 400x400
+  /// working image.
+  pub fn with_search_radius(mut self, radius: f32) -> Self {
+    self.search_radius = radius.max(1.0);
+    return self;
+  }
+
+  /// Fraction of square corners, from 0.0 to 1.0, below which the board is detected again.
+  pub fn with_min_confidence(mut self, confidence: f32) -> Self {
+    self.min_confidence = confidence.clamp(0.0, 1.0);
+    return self;
+  }
+
+  /// Passes the board corners of every frame through `filter` before rectifying the board, so
+  /// the board image and square crops hold still while the camera does.
+  pub fn with_smoothing(mut self, filter: CornerFilter) -> Self {
+    self.smoothing = Some(filter);
+    return self;
+  }
+
+  /// Starts tracking from a board found by other means.
+  pub fn seed(&mut self, segmentation: BoardSegmentation) {
+    self.last = Some(segmentation);
+  }
+
+  /// Forgets the board so the next frame is detected from scratch.
+  pub fn reset(&mut self) {
+    self.last = None;
+    self.confidence = 0.0;
+    self.tracked = false;
+    if let Some(filter) = &mut self.smoothing {
+      filter.reset();
+    }
+  }
+
+  pub fn segmentation(&self) -> Option<&BoardSegmentation> {
+    return self.last.as_ref();
+  }
+
+  /// Fraction of inner square corners confirmed in the last frame.
+  pub fn confidence(&self) -> f32 {
+    return self.confidence;
+  }
+
+  /// Whether the last frame was tracked rather than detected from scratch.
+  pub fn was_tracked(&self) -> bool {
+    return self.tracked;
+  }
+
+  /// Locates the board in the next frame of the stream.
+  pub fn track(&mut self, frame: &DynamicImage) -> Result<&BoardSegmentation, SegmentError> {
+    let (formatted_rgb, formatted_gray) = crate::working_images(frame);
+    let (input_width, input_height) = frame.dimensions();
+    let (w, h) = formatted_gray.dimensions();
+    let to_working = Projection::scale(
+      w as f32 / input_width as f32,
+      h as f32 / input_height as f32,
+    );
+
+    let tracked = self.last.as_ref().and_then(|last| {
+      let predicted = last.projection * to_working.invert();
+      self.refine(&formatted_gray, predicted, last.board.dimensions())
+    });
+
+    self.tracked = false;
+    let mut segmentation = match tracked {
+      Some((projection, confidence)) if confidence >= self.min_confidence => {
+        self.confidence = confidence;
+        self.tracked = true;
+        // a board followed from the last frame has not turned, so its rotation is kept
+        let rotation = self.last.as_ref().map(|last| last.rotation);
+        crate::board_segmentation(
+          &formatted_rgb,
+          projection,
+          (input_width, input_height),
+          rotation,
+        )?
+      }
+      _ => {
+        let detected = crate::segment(frame)?;
+        let predicted = detected.projection * to_working.invert();
+        self.confidence = self
+          .refine(&formatted_gray, predicted, detected.board.dimensions())
+          .map_or(0.0, |(_, confidence)| confidence);
+        detected
+      }
+    };
+
+    if let Some(filter) = &mut self.smoothing {
+      let smoothed = filter.update(segmentation.corners);
+      if smoothed != segmentation.corners {
+        let (width, height) = segmentation.board.dimensions();
+        let (width, height) = (width as f32, height as f32);
+        let board = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+        let working: Vec<(f32, f32)> = smoothed.iter().map(|c| to_working * *c).collect();
+        if let Some(projection) = fit_projection(&working, &board) {
+          segmentation = crate::board_segmentation(
+            &formatted_rgb,
+            projection,
+            (input_width, input_height),
+            Some(segmentation.rotation),
+          )?;
+        }
+      }
+    }
+
+    self.last = Some(segmentation);
+    return Ok(self.last.as_ref().unwrap());
+  }
+
+  // searches for the inner square corners around where `predicted`, mapping working
+  // coordinates onto a board of `board_size`, puts them and fits a projection to them
+  fn refine(
+    &self,
+    gray: &GrayImage,
+    predicted: Projection,
+    (board_width, board_height): (u32, u32),
+  ) -> Option<(Projection, f32)> {
+    let inverse = predicted.invert();
+    let squares = (LATTICE_SIZE - 1) as f32;
+    let on_board = |row: usize, column: usize| {
+      (
+        column as f32 * board_width as f32 / squares,
+        row as f32 * board_height as f32 / squares,
+      )
+    };
+
+    // the border is left out as the board edge is rarely a clean corner
+    let mut from = Vec::new();
+    let mut to = Vec::new();
+    for row in 1..LATTICE_SIZE - 1 {
+      for column in 1..LATTICE_SIZE - 1 {
+        let expected = inverse * on_board(row, column);
+        let neighbour = inverse * on_board(row, column + 1);
+        let spacing =
+          ((neighbour.0 - expected.0).powf(2.0) + (neighbour.1 - expected.1).powf(2.0)).sqrt();
+        // stay well clear of the neighbouring corners
+        let radius = self.search_radius.min(spacing * 0.4);
+        if let Some(found) = strongest_corner(gray, expected, radius) {
+          from.push(refine_corner(gray, found).unwrap_or(found));
+          to.push(on_board(row, column));
+        }
+      }
+    }
+
+    let inner = ((LATTICE_SIZE - 2) * (LATTICE_SIZE - 2)) as f32;
+    let fit = Ransac {
+      threshold: MAX_REPROJECTION_ERROR,
+      ..Ransac::default()
+    }
+    .fit(&from, &to)?;
+    return Some((fit.projection, fit.inliers.len() as f32 / inner));
+  }
+}
+
+// pixel with the highest corner response within `radius` of `centre`
+fn strongest_corner(gray: &GrayImage, (cx, cy): (f32, f32), radius: f32) -> Option<(f32, f32)> {
+  let (width, height) = gray.dimensions();
+  let x0 = (cx - radius).floor().max(0.0) as u32;
+  let y0 = (cy - radius).floor().max(0.0) as u32;
+  let x1 = ((cx + radius).ceil().max(0.0) as u32).min(width.saturating_sub(1));
+  let y1 = ((cy + radius).ceil().max(0.0) as u32).min(height.saturating_sub(1));
+
+  let mut best: Option<((f32, f32), f32)> = None;
+  for y in y0..=y1 {
+    for x in x0..=x1 {
+      let response = match corner_response(gray, x, y) {
+        Some(response) if response > MIN_CORNER_RESPONSE => response,
+        _ => continue,
+      };
+      if best.is_none_or(|(_, r)| response > r) {
+        best = Some(((x as f32, y as f32), response));
+      }
+    }
+  }
+  return best.map(|(point, _)| point);
+}
+
+#[cfg(test)]
+fn render_frame(corners: [(f32, f32); 4]) -> (DynamicImage, Projection) {
+  use crate::checkerboard::{Checkerboard, WOOD};
+
+  let board = Checkerboard {
+    background: image::Rgb([90, 90, 90]),
+    ..Checkerboard::through(corners).with_colors(WOOD)
+  };
+  let frame = board.rgb(400, 400);
+  // to a board 400 pixels wide, as the segmentation expects
+  return (
+    DynamicImage::ImageRgb8(frame),
+    Projection::scale(50.0, 50.0) * board.to_board,
+  );
+}
+
+#[test]
+fn should_track_board_between_frames() {
+  let corners = [(60.0, 50.0), (340.0, 70.0), (350.0, 350.0), (40.0, 330.0)];
+  let (first, projection) = render_frame(corners);
+  let mut tracker = BoardTracker::new();
+  let mut seed = crate::board_segmentation(&first.to_rgb8(), projection, (400, 400), None).unwrap();
+  // detection never picks this rotation for a board without pieces, so it comes from the seed
+  seed.rotation = crate::Rotation::Rotate180;
+  tracker.seed(seed);
+
+  let moved = [(64.0, 53.0), (343.0, 72.0), (354.0, 353.0), (43.0, 334.0)];
+  let (second, _) = render_frame(moved);
+  let segmentation = tracker.track(&second).unwrap();
+  for (found, expected) in segmentation.corners.iter().zip(moved.iter()) {
+    assert!(
+      (found.0 - expected.0).abs() < 1.5 && (found.1 - expected.1).abs() < 1.5,
+      "{:?} is not close to {:?}",
+      found,
+      expected
+    );
+  }
+  assert_eq!(segmentation.rotation, crate::Rotation::Rotate180);
+  assert!(tracker.was_tracked());
+  assert!(tracker.confidence() > 0.9);
+}
@@ -0,0 +1,343 @@
+// Splits the lines found in an image into the two directions of the board edges. Under
+// perspective the lines of each direction are not parallel but meet in a vanishing point, so
+// each family is described by that point rather than by an angle.
+
+use crate::cluster::{Clusterer, KMeans};
+use crate::line::Line;
+
+// sine of the largest angle between a line and the direction to the vanishing point of its
+// family, about as coarse as the one degree steps of the hough transform
+const MAX_ANGLE_ERROR: f32 = 0.05;
+
+// rounds of estimating the vanishing points and reassigning the lines to them
+const ROUNDS: usize = 3;
+
+/// Point where the lines of one direction on the board meet, in homogeneous image
+/// coordinates scaled to unit length.
+///
+/// `w` is zero when the lines are parallel in the image, so the board is not tilted away from
+/// the camera along them. The closer the point lies to the image, the stronger the tilt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VanishingPoint {
+  pub x: f32,
+  pub y: f32,
+  pub w: f32,
+}
+
+impl VanishingPoint {
+  fn new(x: f32, y: f32, w: f32) -> Self {
+    let length = (x * x + y * y + w * w).sqrt();
+    return VanishingPoint {
+      x: x / length,
+      y: y / length,
+      w: w / length,
+    };
+  }
+
+  /// Position in the image, `None` when the lines are parallel.
+  pub fn position(&self) -> Option<(f32, f32)> {
+    if self.w.abs() < 1e-6 {
+      return None;
+    }
+    return Some((self.x / self.w, self.y / self.w));
+  }
+
+  /// Unit direction, up to sign, of the line through `point` that meets the others here.
+  pub fn direction_at(&self, point: (f32, f32)) -> (f32, f32) {
+    let (x, y) = (self.x - point.0 * self.w, self.y - point.1 * self.w);
+    let length = (x * x + y * y).sqrt();
+    if length == 0.0 {
+      return (1.0, 0.0);
+    }
+    return (x / length, y / length);
+  }
+
+  /// The same point after scaling the image by `(sx, sy)`.
+  pub fn scale(&self, sx: f32, sy: f32) -> Self {
+    return VanishingPoint::new(self.x * sx, self.y * sy, self.w);
+  }
+}
+
+/// The lines of each of the two board directions, by index into the grouped lines, and where
+/// they meet. Lines of neither family are left out.
+#[derive(Clone, Debug)]
+pub struct LineFamilies {
+  pub families: [Vec<usize>; 2],
+  pub vanishing_points: [VanishingPoint; 2],
+}
+
+impl LineFamilies {
+  /// Every point where a line of one family crosses a line of the other.
+  pub fn intersections(&self, lines: &[Line]) -> Vec<(f32, f32)> {
+    let mut points = Vec::new();
+    for a in self.families[0].iter() {
+      for b in self.families[1].iter() {
+        if let Some(point) = lines[*a].intersection(&lines[*b]) {
+          points.push(point);
+        }
+      }
+    }
+    return points;
+  }
+}
+
+// maps image coordinates to around the origin and unit size, where homogeneous coordinates are
+// well conditioned
+#[derive(Clone, Copy, Debug)]
+struct Normalisation {
+  centre: (f32, f32),
+  scale: f32,
+}
+
+impl Normalisation {
+  fn of(lines: &[Line]) -> Self {
+    let ends: Vec<(f32, f32)> = lines.iter().flat_map(|l| vec![l.start, l.end]).collect();
+    let min_x = ends.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+    let max_x = ends.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+    let min_y = ends.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    let max_y = ends.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+    let extent = (max_x - min_x).max(max_y - min_y) / 2.0;
+    return Normalisation {
+      centre: ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0),
+      scale: if extent > 0.0 { extent } else { 1.0 },
+    };
+  }
+
+  fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+    return (
+      (x - self.centre.0) / self.scale,
+      (y - self.centre.1) / self.scale,
+    );
+  }
+
+  fn undo(&self, v: [f64; 3]) -> VanishingPoint {
+    let (x, y, w) = (v[0] as f32, v[1] as f32, v[2] as f32);
+    return VanishingPoint::new(
+      x * self.scale + w * self.centre.0,
+      y * self.scale + w * self.centre.1,
+      w,
+    );
+  }
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+  return [
+    a[1] * b[2] - a[2] * b[1],
+    a[2] * b[0] - a[0] * b[2],
+    a[0] * b[1] - a[1] * b[0],
+  ];
+}
+
+// a line in normalised coordinates, with its homogeneous form scaled to a unit normal
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+  homogeneous: [f64; 3],
+  middle: (f64, f64),
+  direction: (f64, f64),
+}
+
+impl Segment {
+  fn new(line: &Line, normalisation: &Normalisation) -> Option<Self> {
+    let (x0, y0) = normalisation.apply(line.start);
+    let (x1, y1) = normalisation.apply(line.end);
+    let (x0, y0, x1, y1) = (x0 as f64, y0 as f64, x1 as f64, y1 as f64);
+    let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+    if length == 0.0 {
+      return None;
+    }
+    let l = cross([x0, y0, 1.0], [x1, y1, 1.0]);
+    let normal = (l[0] * l[0] + l[1] * l[1]).sqrt();
+    return Some(Segment {
+      homogeneous: [l[0] / normal, l[1] / normal, l[2] / normal],
+      middle: ((x0 + x1) / 2.0, (y0 + y1) / 2.0),
+      direction: ((x1 - x0) / length, (y1 - y0) / length),
+    });
+  }
+
+  // sine of the angle between the segment and the direction from it to `v`
+  fn error(&self, v: [f64; 3]) -> f64 {
+    let (x, y) = (v[0] - self.middle.0 * v[2], v[1] - self.middle.1 * v[2]);
+    let length = (x * x + y * y).sqrt();
+    if length == 0.0 {
+      return 0.0;
+    }
+    return (self.direction.0 * y - self.direction.1 * x).abs() / length;
+  }
+}
+
+// eigenvector of the smallest eigenvalue of a symmetric matrix, by jacobi rotations
+#[allow(clippy::needless_range_loop)]
+fn smallest_eigenvector(mut a: [[f64; 3]; 3]) -> [f64; 3] {
+  let mut vectors = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+  for _ in 0..16 {
+    for (p, q) in [(0, 1), (0, 2), (1, 2)].iter().copied() {
+      if a[p][q].abs() < 1e-15 {
+        continue;
+      }
+      let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+      let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+      let c = 1.0 / (t * t + 1.0).sqrt();
+      let s = t * c;
+      for k in 0..3 {
+        let (akp, akq) = (a[k][p], a[k][q]);
+        a[k][p] = c * akp - s * akq;
+        a[k][q] = s * akp + c * akq;
+      }
+      for k in 0..3 {
+        let (apk, aqk) = (a[p][k], a[q][k]);
+        a[p][k] = c * apk - s * aqk;
+        a[q][k] = s * apk + c * aqk;
+      }
+      for row in vectors.iter_mut() {
+        let (vp, vq) = (row[p], row[q]);
+        row[p] = c * vp - s * vq;
+        row[q] = s * vp + c * vq;
+      }
+    }
+  }
+  let smallest = (0..3)
+    .min_by(|i, j| a[*i][*i].partial_cmp(&a[*j][*j]).unwrap())
+    .unwrap();
+  return [
+    vectors[0][smallest],
+    vectors[1][smallest],
+    vectors[2][smallest],
+  ];
+}
+
+// the vanishing point most of `family` agree on, refitted to those in the least squares sense
+fn estimate(segments: &[Segment], family: &[usize]) -> Option<[f64; 3]> {
+  let mut best: Option<(usize, f64, [f64; 3])> = None;
+  for (n, a) in family.iter().enumerate() {
+    for b in family[n + 1..].iter() {
+      let v = cross(segments[*a].homogeneous, segments[*b].homogeneous);
+      if v.iter().all(|c| c.abs() < 1e-12) {
+        continue;
+      }
+      let errors: Vec<f64> = family
+        .iter()
+        .map(|line| segments[*line].error(v))
+        .filter(|error| *error <= MAX_ANGLE_ERROR as f64)
+        .collect();
+      let total: f64 = errors.iter().sum();
+      if best.is_none_or(|(count, best_total, _)| {
+        errors.len() > count || (errors.len() == count && total < best_total)
+      }) {
+        best = Some((errors.len(), total, v));
+      }
+    }
+  }
+  let (_, _, v) = best?;
+
+  // the point closest to all agreeing lines minimises the sum of (l . v)^2 over unit v
+  let mut scatter = [[0.0; 3]; 3];
+  for line in family.iter() {
+    let segment = segments[*line];
+    if segment.error(v) > MAX_ANGLE_ERROR as f64 {
+      continue;
+    }
+    let l = segment.homogeneous;
+    for i in 0..3 {
+      for j in 0..3 {
+        scatter[i][j] += l[i] * l[j];
+      }
+    }
+  }
+  return Some(smallest_eigenvector(scatter));
+}
+
+/// Splits `lines` into the two families of board edges by the vanishing points they meet in.
+/// The lines are first split by orientation, then each family's vanishing point is estimated
+/// from the pairs of its lines most others agree with and every line is moved to the family it
+/// agrees with best. Lines that agree with neither, such as the edges of pieces, are dropped.
+/// `None` when either family ends up with fewer than two lines.
+pub fn group_lines(lines: &[Line]) -> Option<LineFamilies> {
+  let normalisation = Normalisation::of(lines);
+  // lines are referred to by their position here until the families are reported
+  let (indices, segments): (Vec<usize>, Vec<Segment>) = lines
+    .iter()
+    .enumerate()
+    .filter_map(|(index, line)| Some((index, Segment::new(line, &normalisation)?)))
+    .unzip();
+  if segments.len() < 4 {
+    return None;
+  }
+
+  // orientations are doubled so that lines pointing either way along a direction coincide
+  let orientations: Vec<(f32, f32)> = segments
+    .iter()
+    .map(|segment| {
+      let angle = 2.0 * segment.direction.1.atan2(segment.direction.0);
+      (angle.cos() as f32, angle.sin() as f32)
+    })
+    .collect();
+  let clustering = KMeans::new(2).cluster(&orientations);
+  if clustering.len() < 2 {
+    return None;
+  }
+  let mut families = [clustering.members(0), clustering.members(1)];
+
+  let mut points = [[0.0; 3]; 2];
+  for _ in 0..ROUNDS {
+    for (family, point) in families.iter().zip(points.iter_mut()) {
+      *point = estimate(&segments, family)?;
+    }
+    let mut regrouped = [Vec::new(), Vec::new()];
+    for (position, segment) in segments.iter().enumerate() {
+      let errors = [segment.error(points[0]), segment.error(points[1])];
+      let closest = if errors[0] <= errors[1] { 0 } else { 1 };
+      if errors[closest] <= MAX_ANGLE_ERROR as f64 {
+        regrouped[closest].push(position);
+      }
+    }
+    if regrouped.iter().any(|family| family.len() < 2) {
+      return None;
+    }
+    families = regrouped;
+  }
+
+  let [first, second] = families;
+  let to_lines = |family: Vec<usize>| family.iter().map(|position| indices[*position]).collect();
+  return Some(LineFamilies {
+    families: [to_lines(first), to_lines(second)],
+    vanishing_points: [normalisation.undo(points[0]), normalisation.undo(points[1])],
+  });
+}
+
+#[test]
+fn should_group_lines_by_vanishing_point() {
+  // the edges of a board tilted away from the camera, so its columns converge above the image
+  // while its rows stay level, crossed by a few lines of neither direction
+  let mut lines = Vec::new();
+  let apex = (200.0, -600.0);
+  for column in 0..9 {
+    let bottom = (40.0 + column as f32 * 40.0, 380.0);
+    let t = 400.0 / (bottom.1 - apex.1);
+    let top = (
+      bottom.0 + (apex.0 - bottom.0) * t,
+      bottom.1 + (apex.1 - bottom.1) * t,
+    );
+    lines.push(Line::new(top, bottom));
+  }
+  for row in 0..9 {
+    let y = 20.0 + row as f32 * 42.0;
+    lines.push(Line::new((0.0, y), (400.0, y + 0.3)));
+  }
+  lines.push(Line::new((0.0, 0.0), (400.0, 400.0)));
+  lines.push(Line::new((0.0, 330.0), (400.0, 60.0)));
+
+  let grouped = group_lines(&lines).unwrap();
+  let mut families = grouped.families.clone();
+  families.sort();
+  assert_eq!(families[0], (0..9).collect::<Vec<usize>>());
+  assert_eq!(families[1], (9..18).collect::<Vec<usize>>());
+
+  let columns = if grouped.families[0][0] == 0 { 0 } else { 1 };
+  let (x, y) = grouped.vanishing_points[columns].position().unwrap();
+  assert!((x - apex.0).abs() < 1.0 && (y - apex.1).abs() < 5.0);
+  // the rows barely converge, far to the side
+  let rows = grouped.vanishing_points[1 - columns];
+  assert!(rows.position().is_none_or(|(x, _)| x.abs() > 10000.0));
+  let (dx, dy) = rows.direction_at((200.0, 200.0));
+  assert!(dy.abs() < 0.01 * dx.abs());
+}